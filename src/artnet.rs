@@ -0,0 +1,64 @@
+use anyhow::{ensure, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::output::Output;
+
+/// UDP port Art-Net nodes listen on.
+pub const PORT: u16 = 6454;
+
+/// OpCode for an ArtDmx packet.
+const OP_DMX: u16 = 0x5000;
+/// Art-Net protocol revision.
+const VERSION: u16 = 14;
+
+/// Minimal Art-Net sender, speaking only ArtDmx.
+///
+/// Universes are numbered from 1 like E1.31, so universe 1 is sent as Art-Net port address 0.
+pub struct ArtNet {
+    socket: UdpSocket,
+    /// Destination UDP port
+    port: u16,
+    /// Next sequence number for each universe
+    seq: HashMap<u16, u8>,
+}
+
+impl ArtNet {
+    pub fn new(port: u16) -> Result<Self> {
+        Ok(Self { socket: UdpSocket::bind("0.0.0.0:0")?, port, seq: HashMap::new() })
+    }
+}
+
+impl Output for ArtNet {
+    fn send(&mut self, addr: &IpAddr, universe: u16, dmx: &[u8]) -> Result<()> {
+        ensure!((1..=0x8000).contains(&universe), "Universe {universe} can't be sent over Art-Net");
+
+        // Sequence numbers run from 1..=255, 0 disables sequencing
+        let seq = self.seq.entry(universe).or_insert(1);
+        let packet = encode(*seq, universe - 1, dmx);
+        *seq = seq.wrapping_add(1).max(1);
+
+        self.socket.send_to(&packet, SocketAddr::new(*addr, self.port))?;
+        Ok(())
+    }
+}
+
+/// Encode a single ArtDmx packet for a 15 bit port address.
+pub fn encode(seq: u8, port_address: u16, dmx: &[u8]) -> Vec<u8> {
+    // Art-Net has no start code, and the data length must be even and at least 2
+    let data = dmx.get(1..).unwrap_or_default();
+    let len = (data.len() + data.len() % 2).max(2);
+
+    let mut p = Vec::with_capacity(18 + len);
+    p.extend(b"Art-Net\0");
+    p.extend(OP_DMX.to_le_bytes());
+    p.extend(VERSION.to_be_bytes());
+    p.push(seq);
+    p.push(0); // physical port
+    p.extend(port_address.to_le_bytes()); // SubUni, then Net
+    p.extend((len as u16).to_be_bytes());
+    p.extend(data);
+    p.resize(18 + len, 0);
+
+    p
+}
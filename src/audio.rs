@@ -0,0 +1,543 @@
+use anyhow::{bail, Context, Result};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Samples per onset envelope frame.
+const HOP: usize = 256;
+/// Seconds of onset envelope to analyze.
+const HISTORY: f64 = 8.0;
+/// Seconds between tempo estimates.
+const INTERVAL: f64 = 0.5;
+/// Number of beat multiples to compare when estimating tempo.
+const MULTIPLES: usize = 4;
+/// Estimates older than this are ignored, e.g. when a file ends or a device stops delivering audio.
+const STALE: Duration = Duration::from_secs(2);
+
+/// The tempo and phase of the music.
+#[derive(Clone, Copy, Debug)]
+pub struct Estimate {
+    /// Tempo in beats per minute
+    pub bpm: f64,
+    /// Fraction of the current beat elapsed, from `0..1`
+    pub phase: f64,
+    /// Position in the current 4 beat bar, from `0..4`, if the downbeat stands out
+    pub bar: Option<f64>,
+    /// How periodic the music is, from `0..1`
+    pub confidence: f64,
+}
+
+impl Estimate {
+    /// Project the estimate `dt` seconds into the future.
+    pub fn advance(mut self, dt: f64) -> Self {
+        let beats = dt * self.bpm / 60.0;
+        self.phase = (self.phase + beats).rem_euclid(1.0);
+        self.bar = self.bar.map(|bar| (bar + beats).rem_euclid(4.0));
+        self
+    }
+}
+
+/// Onset and tempo detector for a mono audio stream.
+pub struct Detector {
+    /// Onset envelope frames per second
+    fps: f64,
+    /// Tempo range to search, in BPM
+    min_bpm: f64,
+    max_bpm: f64,
+
+    /// Samples not yet making up a full frame
+    pending: Vec<f32>,
+    /// Low-passed signal state, to pick out kicks
+    low: f64,
+    /// Log energy of the previous frame, in the full and low bands
+    prev: (f64, f64),
+    /// Onset strength of each recent frame, oldest first
+    env: VecDeque<f64>,
+    /// Frames since the last estimate
+    since: usize,
+
+    estimate: Option<Estimate>,
+}
+
+impl Detector {
+    pub fn new(sample_rate: u32, min_bpm: f64, max_bpm: f64) -> Self {
+        Self {
+            fps: sample_rate as f64 / HOP as f64,
+            min_bpm,
+            max_bpm,
+            pending: Vec::with_capacity(HOP),
+            low: 0.0,
+            prev: (0.0, 0.0),
+            env: VecDeque::new(),
+            since: 0,
+            estimate: None,
+        }
+    }
+
+    /// Feed in mono samples, returning a new estimate if one is due.
+    pub fn process(&mut self, samples: &[f32]) -> Option<Estimate> {
+        let mut updated = false;
+
+        for &x in samples {
+            self.pending.push(x);
+            if self.pending.len() == HOP {
+                self.frame();
+                self.pending.clear();
+
+                self.since += 1;
+                if self.since as f64 >= INTERVAL * self.fps {
+                    self.since = 0;
+                    self.estimate = self.estimate();
+                    updated = true;
+                }
+            }
+        }
+
+        match updated {
+            true => self.latest(),
+            false => None,
+        }
+    }
+
+    /// The latest estimate, as of the end of the audio processed so far.
+    pub fn latest(&self) -> Option<Estimate> {
+        let since = (self.since * HOP + self.pending.len()) as f64 / (self.fps * HOP as f64);
+        self.estimate.map(|e| e.advance(since))
+    }
+
+    /// Compute the onset strength of a single frame.
+    fn frame(&mut self) {
+        // One-pole lowpass at roughly 150Hz
+        let a = 1.0 - (-2.0 * std::f64::consts::PI * 150.0 / (self.fps * HOP as f64)).exp();
+
+        let (mut full, mut low) = (0.0, 0.0);
+        for &x in &self.pending {
+            let x = x as f64;
+            self.low += a * (x - self.low);
+            full += x * x;
+            low += self.low * self.low;
+        }
+
+        // Half-wave rectified rise in log energy
+        let (full, low) = ((full / HOP as f64 + 1e-9).ln(), (low / HOP as f64 + 1e-9).ln());
+        let onset = (full - self.prev.0).max(0.0) + (low - self.prev.1).max(0.0);
+        self.prev = (full, low);
+
+        self.env.push_back(onset);
+        if self.env.len() as f64 > HISTORY * self.fps {
+            self.env.pop_front();
+        }
+    }
+
+    fn estimate(&self) -> Option<Estimate> {
+        let n = self.env.len();
+        let max_lag = (MULTIPLES as f64 * 60.0 * self.fps / self.min_bpm).ceil() as usize + 1;
+        // Need at least a couple of periods at the slowest tempo
+        if n < max_lag + (60.0 * self.fps / self.min_bpm) as usize {
+            return None;
+        }
+
+        // Autocorrelation of the mean-removed envelope
+        let mean = self.env.iter().sum::<f64>() / n as f64;
+        let env = self.env.iter().map(|e| e - mean).collect::<Vec<_>>();
+        let r = (0..=max_lag)
+            .map(|lag| (lag..n).map(|t| env[t] * env[t - lag]).sum::<f64>() / (n - lag) as f64)
+            .collect::<Vec<_>>();
+        if r[0] <= 0.0 {
+            return None;
+        }
+        let at = |lag: f64| {
+            let i = lag.floor() as usize;
+            let fr = lag - i as f64;
+            r[i] * (1.0 - fr) + r[(i + 1).min(max_lag)] * fr
+        };
+
+        // Score each candidate tempo by how well the envelope lines up with itself a few beats later,
+        // favoring tempos near 120 to settle octave ambiguity.
+        let (mut best_bpm, mut best_score, mut best_corr) = (0.0, f64::MIN, 0.0);
+        let mut bpm = self.min_bpm;
+        while bpm <= self.max_bpm {
+            let period = 60.0 * self.fps / bpm;
+            let corr = (1..=MULTIPLES).map(|k| at(k as f64 * period)).sum::<f64>() / MULTIPLES as f64 / r[0];
+            let prior = (-0.5 * (bpm / 120.0).log2().powi(2)).exp();
+            let score = corr * prior;
+            if score > best_score {
+                (best_bpm, best_score, best_corr) = (bpm, score, corr);
+            }
+            bpm += 0.05;
+        }
+
+        // Find the offset from the end of the envelope to the most recent beat
+        let period = 60.0 * self.fps / best_bpm;
+        let beats = ((n - 1) as f64 / period).floor() as usize;
+        let sample = |t: f64| {
+            let i = t.round() as isize;
+            (i - 1..=i + 1).filter(|&i| i >= 0 && (i as usize) < n).map(|i| env[i as usize]).fold(0.0, f64::max)
+        };
+        let (mut best_ofs, mut best_sum) = (0.0, f64::MIN);
+        let mut ofs = 0.0;
+        while ofs < period {
+            let sum = (0..beats).map(|j| sample((n - 1) as f64 - ofs - j as f64 * period)).sum::<f64>();
+            if sum > best_sum {
+                (best_ofs, best_sum) = (ofs, sum);
+            }
+            ofs += 0.5;
+        }
+        let phase = best_ofs / period;
+
+        // The downbeat is whichever beat of the bar is accented the most
+        let mut bins = [0.0; 4];
+        for j in 0..beats {
+            bins[j % 4] += sample((n - 1) as f64 - best_ofs - j as f64 * period).max(0.0);
+        }
+        let (down, &strongest) = bins.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap();
+        let average = bins.iter().sum::<f64>() / 4.0;
+        let bar = (strongest > average * 1.15).then_some(down as f64 + phase);
+
+        Some(Estimate { bpm: best_bpm, phase, bar, confidence: best_corr.clamp(0.0, 1.0) })
+    }
+}
+
+/// Where to get audio from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// A WAV or FLAC file, played back in real time
+    File(PathBuf),
+    /// A capture device matching a name, or the default one
+    Device(Option<String>),
+}
+
+impl Source {
+    /// Parse a path ending in `.wav` or `.flac`, `default`, or a capture device name.
+    pub fn parse(s: &str) -> Self {
+        let lower = s.to_lowercase();
+        if lower.ends_with(".wav") || lower.ends_with(".flac") {
+            Source::File(s.into())
+        } else if lower == "default" {
+            Source::Device(None)
+        } else {
+            Source::Device(Some(s.into()))
+        }
+    }
+
+    /// Start streaming mono samples into `tx`, returning the sample rate.
+    pub fn open(self, tx: mpsc::Sender<Vec<f32>>) -> Result<u32> {
+        match self {
+            Source::File(path) => {
+                let (samples, rate) = load(&path)?;
+                log::info!("Playing {} ({:.0}s)", path.display(), samples.len() as f64 / rate as f64);
+                thread::spawn(move || play(samples, rate, tx));
+                Ok(rate)
+            }
+            Source::Device(name) => capture(name, tx),
+        }
+    }
+}
+
+/// Decode a WAV or FLAC file into mono samples, returning them with the sample rate.
+pub fn load(path: impl AsRef<Path>) -> Result<(Vec<f32>, u32)> {
+    let path = path.as_ref();
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let ctx = || format!("Failed to decode {}", path.display());
+
+    let (samples, rate, channels) = match ext.as_str() {
+        "wav" => {
+            let mut reader = hound::WavReader::open(path).with_context(ctx)?;
+            let spec = reader.spec();
+            let samples = match spec.sample_format {
+                hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<Vec<_>, _>>(),
+                hound::SampleFormat::Int => {
+                    let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                    reader.samples::<i32>().map(|x| x.map(|x| x as f32 * scale)).collect()
+                }
+            };
+            (samples.with_context(ctx)?, spec.sample_rate, spec.channels as usize)
+        }
+        "flac" => {
+            let mut reader = claxon::FlacReader::open(path).with_context(ctx)?;
+            let info = reader.streaminfo();
+            let scale = 1.0 / (1u64 << (info.bits_per_sample - 1)) as f32;
+            let samples = reader.samples().map(|x| x.map(|x| x as f32 * scale)).collect::<Result<Vec<_>, _>>();
+            (samples.with_context(ctx)?, info.sample_rate, info.channels as usize)
+        }
+        _ => bail!("Unsupported audio file {}, expected .wav or .flac", path.display()),
+    };
+
+    Ok((mix(&samples, channels), rate))
+}
+
+/// Mix interleaved samples down to mono.
+fn mix(samples: &[f32], channels: usize) -> Vec<f32> {
+    samples.chunks(channels.max(1)).map(|c| c.iter().sum::<f32>() / c.len() as f32).collect()
+}
+
+/// Settings for following the music.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    /// Minimum confidence before an estimate is used
+    pub confidence: f64,
+    /// Seconds after a tap-tempo during which estimates are ignored
+    pub tap_override: f64,
+    /// Tempo range to search, in BPM
+    pub min_bpm: f64,
+    pub max_bpm: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self { confidence: 0.3, tap_override: 30.0, min_bpm: 70.0, max_bpm: 180.0 }
+    }
+}
+
+/// A running beat detector, analyzing audio on its own thread.
+pub struct Audio {
+    pub settings: Settings,
+    /// The latest estimate, and when it was made
+    latest: Arc<Mutex<Option<(Estimate, Instant)>>>,
+}
+
+impl Audio {
+    pub fn spawn(source: Source, settings: Settings) -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<Vec<f32>>();
+
+        let rate = source.open(tx)?;
+        log::info!("Following the beat of the audio at {rate}Hz");
+
+        let latest = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&latest);
+        thread::spawn(move || {
+            let mut detector = Detector::new(rate, settings.min_bpm, settings.max_bpm);
+            for samples in rx {
+                if let Some(estimate) = detector.process(&samples) {
+                    log::trace!("Audio estimate: {estimate:?}");
+                    *shared.lock().unwrap() = Some((estimate, Instant::now()));
+                }
+            }
+        });
+
+        Ok(Self { settings, latest })
+    }
+
+    /// The current estimate, if it's recent and confident enough.
+    pub fn current(&self) -> Option<Estimate> {
+        let (estimate, at) = (*self.latest.lock().unwrap())?;
+        let age = at.elapsed();
+        (age < STALE && estimate.confidence >= self.settings.confidence).then(|| estimate.advance(age.as_secs_f64()))
+    }
+
+    /// The latest estimate regardless of confidence, for display.
+    pub fn latest(&self) -> Option<Estimate> {
+        self.latest
+            .lock()
+            .unwrap()
+            .filter(|(_, at)| at.elapsed() < STALE)
+            .map(|(e, at)| e.advance(at.elapsed().as_secs_f64()))
+    }
+}
+
+/// Stream samples into `tx` at the rate they would play at.
+fn play(samples: Vec<f32>, rate: u32, tx: mpsc::Sender<Vec<f32>>) {
+    let chunk = HOP * 4;
+    let start = Instant::now();
+    for (i, samples) in samples.chunks(chunk).enumerate() {
+        let due = start + Duration::from_secs_f64((i * chunk) as f64 / rate as f64);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        if tx.send(samples.to_vec()).is_err() {
+            return;
+        }
+    }
+    log::info!("Audio file finished");
+}
+
+/// Start capturing from an input device into `tx`, returning its sample rate.
+fn capture(name: Option<String>, tx: mpsc::Sender<Vec<f32>>) -> Result<u32> {
+    let host = cpal::default_host();
+    let device = match &name {
+        Some(name) => host.input_devices()?.find(|d| d.name().map(|n| n.contains(name.as_str())).unwrap_or(false)),
+        None => host.default_input_device(),
+    };
+    let Some(device) = device else {
+        let names = host.input_devices()?.filter_map(|d| d.name().ok()).collect::<Vec<_>>();
+        bail!("No audio input matches {name:?}, available inputs are: {names:?}");
+    };
+
+    let config = device.default_input_config()?;
+    let (rate, channels) = (config.sample_rate().0, config.channels() as usize);
+    log::info!("Capturing audio input `{}`", device.name()?);
+
+    // The stream stops when dropped, so keep it alive on its own thread.
+    let (ok_tx, ok_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let err = |e| log::warn!("Audio input error: {e}");
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => device.build_input_stream(
+                &config.config(),
+                move |data: &[f32], _: &_| drop(tx.send(mix(data, channels))),
+                err,
+                None,
+            ),
+            cpal::SampleFormat::I16 => device.build_input_stream(
+                &config.config(),
+                move |data: &[i16], _: &_| {
+                    let data = data.iter().map(|&x| x as f32 / 32768.0).collect::<Vec<_>>();
+                    drop(tx.send(mix(&data, channels)));
+                },
+                err,
+                None,
+            ),
+            format => return drop(ok_tx.send(Err(anyhow::anyhow!("Unsupported audio sample format {format:?}")))),
+        };
+
+        match stream.map_err(anyhow::Error::from).and_then(|s| s.play().map(|_| s).map_err(anyhow::Error::from)) {
+            Ok(stream) => {
+                ok_tx.send(Ok(())).ok();
+                loop {
+                    thread::park();
+                }
+            }
+            Err(e) => drop(ok_tx.send(Err(e))),
+        }
+    });
+
+    ok_rx.recv()??;
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::{self, State};
+
+    const RATE: u32 = 44100;
+    /// When the first click lands, in seconds.
+    const START: f64 = 0.1;
+
+    /// A click track at `bpm` over a little noise, with a kick on every beat and the downbeat of each bar accented.
+    fn clicks(bpm: f64, secs: f64) -> Vec<f32> {
+        let period = 60.0 / bpm;
+        let kick = |i: usize| {
+            let t = i as f64 / RATE as f64 - START;
+            let beat = (t / period).floor();
+            let dt = t - beat * period;
+            let accent = if beat % 4.0 == 0.0 { 1.0 } else { 0.25 };
+            let kick = (std::f64::consts::TAU * 60.0 * dt).sin() * (-dt * 30.0).exp();
+            if t < 0.0 {
+                0.0
+            } else {
+                kick * accent * 0.8
+            }
+        };
+        noise(secs).iter().enumerate().map(|(i, x)| x * 0.1 + kick(i) as f32).collect()
+    }
+
+    /// Pseudo-random noise, with no beat at all.
+    fn noise(secs: f64) -> Vec<f32> {
+        let mut x = 1u32;
+        (0..(secs * RATE as f64) as usize)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as f32 / u32::MAX as f32 - 0.5
+            })
+            .collect()
+    }
+
+    fn detect(samples: &[f32]) -> Estimate {
+        let settings = Settings::default();
+        let mut detector = Detector::new(RATE, settings.min_bpm, settings.max_bpm);
+        detector.process(samples);
+        detector.latest().expect("No estimate")
+    }
+
+    /// Distance between two positions which wrap around at `len`.
+    fn dist(a: f64, b: f64, len: f64) -> f64 {
+        let d = (a - b).rem_euclid(len);
+        d.min(len - d)
+    }
+
+    #[test]
+    fn click_tracks() {
+        let secs = 12.0;
+        for bpm in [95.0, 110.0, 120.0, 128.0, 140.0] {
+            let est = detect(&clicks(bpm, secs));
+            assert!((est.bpm - bpm).abs() < 1.0, "Detected {} at {bpm} BPM", est.bpm);
+            assert!(est.confidence >= Settings::default().confidence, "Not confident at {bpm} BPM: {est:?}");
+
+            // Where the track is at as the audio ends
+            let beats = (secs - START) * bpm / 60.0;
+            assert!(dist(est.phase, beats, 1.0) < 0.1, "Phase {} should be {} at {bpm} BPM", est.phase, beats.fract());
+            let bar = est.bar.expect("No downbeat");
+            assert!(dist(bar, beats, 4.0) < 0.1, "Bar {bar} should be {} at {bpm} BPM", beats % 4.0);
+        }
+    }
+
+    #[test]
+    fn click_file() {
+        // A 16 bit stereo file, as exported from most DAWs
+        let path = std::env::temp_dir().join(format!("mslive-clicks-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for x in clicks(120.0, 12.0) {
+            let x = (x.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_sample(x).unwrap();
+            writer.write_sample(x).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let loaded = load(&path);
+        std::fs::remove_file(&path).ok();
+        let (samples, rate) = loaded.unwrap();
+        assert_eq!(rate, RATE);
+        let est = detect(&samples);
+        assert!((est.bpm - 120.0).abs() < 1.0, "Detected {} at 120 BPM", est.bpm);
+    }
+
+    #[test]
+    fn confidence_gate() {
+        let audio = |est: Estimate| Audio {
+            settings: Settings::default(),
+            latest: Arc::new(Mutex::new(Some((est, Instant::now())))),
+        };
+
+        // Noise isn't confident enough to follow
+        let est = detect(&noise(12.0));
+        assert!(est.confidence < Settings::default().confidence, "Confident in noise: {est:?}");
+        assert!(audio(est).current().is_none());
+        assert!(audio(est).latest().is_some());
+
+        let est = detect(&clicks(128.0, 12.0));
+        assert!(audio(est).current().is_some());
+    }
+
+    #[test]
+    fn tap_overrides() {
+        let settings = Settings::default();
+        let est = Estimate { bpm: 140.0, phase: 0.5, bar: None, confidence: 1.0 };
+        let mut s = State::new();
+        s.dt = 1.0 / 60.0;
+
+        // Just tapped, so the audio is ignored
+        s.t = 100.0;
+        s.tapped = Some(95.0);
+        let (bpm, phi) = (s.bpm, s.phi_beat);
+        logic::on_audio(&mut s, est, &settings);
+        assert_eq!((s.bpm, s.phi_beat), (bpm, phi));
+
+        // Until a while later
+        s.t = 95.0 + settings.tap_override;
+        logic::on_audio(&mut s, est, &settings);
+        assert!(s.bpm > bpm);
+    }
+}
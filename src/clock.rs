@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Result};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::midi;
+
+/// MIDI clock ticks per quarter note.
+pub const PPQN: f64 = 24.0;
+
+/// Port name to pass to connect a virtual port instead, so other software can connect to us.
+pub const VIRTUAL: &str = "virtual";
+
+/// Tempo range to accept from an incoming clock, to reject glitches.
+const BPM: std::ops::RangeInclusive<f64> = 20.0..=400.0;
+/// The incoming clock is considered gone after this long without a tick.
+const TIMEOUT: f64 = 0.5;
+/// How much each tick interval moves the smoothed tempo, from `0..1`.
+const SMOOTHING: f64 = 0.05;
+
+const TICK: u8 = 0xf8;
+const START: u8 = 0xfa;
+const CONTINUE: u8 = 0xfb;
+const STOP: u8 = 0xfc;
+const SONG_POSITION: u8 = 0xf2;
+
+/// Tracks the tempo and song position of an incoming MIDI clock.
+#[derive(Clone, Debug)]
+pub struct Follower {
+    /// Whether the transport is playing
+    running: bool,
+    /// Ticks since the start of the song. The first tick after a Start is tick 0, so this is -1 until then.
+    ticks: i64,
+    /// Time the last tick arrived, in seconds
+    last: Option<f64>,
+    /// Smoothed seconds per tick
+    period: Option<f64>,
+}
+
+impl Default for Follower {
+    fn default() -> Self {
+        Self { running: false, ticks: -1, last: None, period: None }
+    }
+}
+
+impl Follower {
+    /// Handle a MIDI message received at `t` seconds.
+    pub fn handle(&mut self, msg: &[u8], t: f64) {
+        match *msg {
+            [TICK, ..] => {
+                if let Some(last) = self.last {
+                    let dt = t - last;
+                    // Ignore intervals which are way off, e.g. from a hiccup in delivery
+                    if BPM.contains(&(60.0 / (dt * PPQN))) {
+                        self.period = Some(match self.period {
+                            Some(period) => period + (dt - period) * SMOOTHING,
+                            None => dt,
+                        });
+                    }
+                }
+                self.last = Some(t);
+                if self.running {
+                    self.ticks += 1;
+                }
+            }
+            [START, ..] => {
+                self.running = true;
+                self.ticks = -1;
+            }
+            [CONTINUE, ..] => self.running = true,
+            [STOP, ..] => self.running = false,
+            // Position in 16th notes, each 6 ticks long. The next tick plays that position.
+            [SONG_POSITION, lsb, msb, ..] => self.ticks = (((msb as i64) << 7 | lsb as i64) * 6) - 1,
+            _ => {}
+        }
+    }
+
+    /// Tempo of the clock at `t` seconds, if it's still ticking.
+    pub fn bpm(&self, t: f64) -> Option<f64> {
+        match (self.last, self.period) {
+            (Some(last), Some(period)) if t - last < TIMEOUT => Some(60.0 / (period * PPQN)),
+            _ => None,
+        }
+    }
+
+    /// Beats since the start of the song at `t` seconds, interpolating between ticks, if playing.
+    pub fn beat(&self, t: f64) -> Option<f64> {
+        let (last, period) = (self.last?, self.period?);
+        if !self.running || self.ticks < 0 || t - last >= TIMEOUT {
+            return None;
+        }
+        // Never run past the next tick, in case it's late
+        let fr = ((t - last) / period).clamp(0.0, 1.0);
+        Some((self.ticks as f64 + fr) / PPQN)
+    }
+}
+
+/// Follows the MIDI clock arriving on an input port.
+pub struct ClockIn {
+    start: Instant,
+    follower: Arc<Mutex<Follower>>,
+    /// Full name of the port, unless it's our own virtual one
+    port: Option<String>,
+    _conn: MidiInputConnection<()>,
+}
+
+impl ClockIn {
+    /// Listen on the input port best matching `name`, or a new virtual port if `name` is `virtual`.
+    pub fn open(name: &str) -> Result<Self> {
+        let mut input = MidiInput::new("mslive clock")?;
+        input.ignore(Ignore::None);
+
+        let start = Instant::now();
+        let follower = Arc::new(Mutex::new(Follower::default()));
+        let shared = Arc::clone(&follower);
+        let callback = move |_: u64, msg: &[u8], _: &mut ()| shared.lock().unwrap().handle(msg, start.elapsed().as_secs_f64());
+
+        let (port, conn) = match name {
+            #[cfg(unix)]
+            VIRTUAL => {
+                use midir::os::unix::VirtualInput;
+                log::info!("Following MIDI clock on virtual port `mslive clock`");
+                (None, input.create_virtual("mslive clock", callback, ()).map_err(|e| anyhow!("{e}"))?)
+            }
+            _ => {
+                let ports = input.ports();
+                let names = ports.iter().map(|p| input.port_name(p).unwrap_or_default()).collect::<Vec<_>>();
+                let Some(i) = midi::find(&names, name).and_then(|found| names.iter().position(|n| n == found)) else {
+                    anyhow::bail!("No MIDI input matches `{name}` for the clock, available inputs are: {names:?}");
+                };
+                log::info!("Following MIDI clock on `{}`", names[i]);
+                let conn = input.connect(&ports[i], "mslive clock", callback, ()).map_err(|e| anyhow!("{e}"))?;
+                (Some(names[i].clone()), conn)
+            }
+        };
+
+        Ok(Self { start, follower, port, _conn: conn })
+    }
+
+    /// Full name of the port followed, unless it's our own virtual one.
+    pub fn port(&self) -> Option<&str> {
+        self.port.as_deref()
+    }
+
+    /// Tempo of the incoming clock, if it's ticking.
+    pub fn bpm(&self) -> Option<f64> {
+        self.follower.lock().unwrap().bpm(self.start.elapsed().as_secs_f64())
+    }
+
+    /// Beats since the start of the song, if playing.
+    pub fn beat(&self) -> Option<f64> {
+        self.follower.lock().unwrap().beat(self.start.elapsed().as_secs_f64())
+    }
+}
+
+/// State shared with the thread sending clock out.
+struct Shared {
+    /// Current tempo, as `f64` bits
+    bpm: AtomicU64,
+    /// Set to send a Start before the next tick
+    start: AtomicBool,
+    /// Cleared to send a Stop and shut down
+    running: AtomicBool,
+}
+
+/// Sends MIDI clock derived from our own tempo out of an output port.
+pub struct ClockOut {
+    shared: Arc<Shared>,
+    started: bool,
+    /// `phi` as of the last update, to spot the start of a measure
+    phi: f64,
+}
+
+impl ClockOut {
+    /// Send to the output port best matching `name`, or a new virtual port if `name` is `virtual`.
+    pub fn open(name: &str, bpm: f64) -> Result<Self> {
+        let output = MidiOutput::new("mslive clock")?;
+        let conn = match name {
+            #[cfg(unix)]
+            VIRTUAL => {
+                use midir::os::unix::VirtualOutput;
+                log::info!("Sending MIDI clock on virtual port `mslive clock`");
+                output.create_virtual("mslive clock").map_err(|e| anyhow!("{e}"))?
+            }
+            _ => {
+                let ports = output.ports();
+                let names = ports.iter().map(|p| output.port_name(p).unwrap_or_default()).collect::<Vec<_>>();
+                let Some(i) = midi::find(&names, name).and_then(|found| names.iter().position(|n| n == found)) else {
+                    anyhow::bail!("No MIDI output matches `{name}` for the clock, available outputs are: {names:?}");
+                };
+                log::info!("Sending MIDI clock on `{}`", names[i]);
+                output.connect(&ports[i], "mslive clock").map_err(|e| anyhow!("{e}"))?
+            }
+        };
+
+        let shared = Arc::new(Shared {
+            bpm: AtomicU64::new(bpm.to_bits()),
+            start: AtomicBool::new(false),
+            running: AtomicBool::new(true),
+        });
+        let s = Arc::clone(&shared);
+        thread::spawn(move || send(conn, &s));
+
+        Ok(Self { shared, started: false, phi: 0.0 })
+    }
+
+    /// Follow the engine's tempo. The first time `phi` wraps around to a new measure, the transport is
+    /// started so followers' bars line up with ours.
+    pub fn update(&mut self, bpm: f64, phi: f64) {
+        self.shared.bpm.store(bpm.to_bits(), Ordering::Relaxed);
+        if !self.started && phi < self.phi {
+            self.shared.start.store(true, Ordering::Relaxed);
+            self.started = true;
+        }
+        self.phi = phi;
+    }
+}
+
+impl Drop for ClockOut {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Send ticks on their own thread, since they're due more often than engine frames.
+fn send(mut conn: MidiOutputConnection, shared: &Shared) {
+    let mut next = Instant::now();
+
+    while shared.running.load(Ordering::Relaxed) {
+        if shared.start.swap(false, Ordering::Relaxed) {
+            conn.send(&[SONG_POSITION, 0, 0]).ok();
+            conn.send(&[START]).ok();
+            next = Instant::now();
+        }
+
+        if let Err(e) = conn.send(&[TICK]) {
+            log::debug!("Failed to send MIDI clock: {e}");
+        }
+
+        // Schedule from the previous deadline rather than now, so timing errors don't accumulate
+        let bpm = f64::from_bits(shared.bpm.load(Ordering::Relaxed)).clamp(*BPM.start(), *BPM.end());
+        next += Duration::from_secs_f64(60.0 / (bpm * PPQN));
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            None => next = Instant::now(),
+        }
+    }
+
+    conn.send(&[STOP]).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `n` ticks at `bpm`, starting at `t` seconds, with a little jitter. Returns the time of the last tick.
+    fn ticks(f: &mut Follower, bpm: f64, t: f64, n: usize) -> f64 {
+        let period = 60.0 / (bpm * PPQN);
+        let mut last = t;
+        for i in 0..n {
+            let jitter = [0.0, 0.0004, -0.0003, 0.0002, -0.0004][i % 5];
+            last = t + i as f64 * period + jitter;
+            f.handle(&[TICK], last);
+        }
+        last
+    }
+
+    #[test]
+    fn tempo() {
+        let mut f = Follower::default();
+        assert_eq!(f.bpm(0.0), None);
+
+        let last = ticks(&mut f, 125.0, 0.0, 24 * 16);
+        let bpm = f.bpm(last).unwrap();
+        assert!((bpm - 125.0).abs() < 0.5, "{bpm}");
+
+        // Stopped ticking
+        assert_eq!(f.bpm(last + 2.0 * TIMEOUT), None);
+    }
+
+    #[test]
+    fn glitches() {
+        let mut f = Follower::default();
+        let last = ticks(&mut f, 128.0, 0.0, 24 * 8);
+
+        // A hiccup in delivery doesn't throw the tempo off
+        let last = ticks(&mut f, 128.0, last + 0.2, 24);
+        let bpm = f.bpm(last).unwrap();
+        assert!((bpm - 128.0).abs() < 0.5, "{bpm}");
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let mut f = Follower::default();
+        let period = 60.0 / (120.0 * PPQN);
+
+        // Ticking, but not playing yet
+        let last = ticks(&mut f, 120.0, 0.0, 48);
+        assert_eq!(f.beat(last), None);
+
+        // The first tick after a start is the start of the song
+        f.handle(&[START], last + period / 2.0);
+        let t = last + period;
+        f.handle(&[TICK], t);
+        assert_eq!(f.beat(t), Some(0.0));
+
+        // Two beats later, halfway to the next tick
+        let last = ticks(&mut f, 120.0, t + period, 48);
+        let beat = f.beat(last + period / 2.0).unwrap();
+        assert!((beat - (48.5 / PPQN)).abs() < 0.02, "{beat}");
+
+        f.handle(&[STOP], last + period / 2.0);
+        assert_eq!(f.beat(last + period), None);
+        assert!(f.bpm(last + period).is_some());
+
+        // Continue picks up where it stopped
+        f.handle(&[CONTINUE], last + period);
+        f.handle(&[TICK], last + period);
+        assert_eq!(f.beat(last + period), Some(49.0 / PPQN));
+    }
+
+    #[test]
+    fn song_position() {
+        let mut f = Follower::default();
+        let period = 60.0 / (120.0 * PPQN);
+        let last = ticks(&mut f, 120.0, 0.0, 24);
+
+        // 16th note 200, 50 beats in, split into 7 bit halves
+        f.handle(&[SONG_POSITION, 200 & 0x7f, 200 >> 7], last + period / 2.0);
+        f.handle(&[CONTINUE], last + period / 2.0);
+        f.handle(&[TICK], last + period);
+        assert_eq!(f.beat(last + period), Some(50.0));
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use crate::logic::{Aftertouch, AftertouchCurve, Param, Quantize};
+use crate::output::Protocol;
+use crate::tap::Snap;
+
+/// Settings loaded from `mslive.toml`. Anything given on the command line takes precedence.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub dmx: DmxConfig,
+    pub midi: MidiConfig,
+    pub audio: AudioConfig,
+    pub link: LinkConfig,
+    pub tap: TapConfig,
+    pub launch: LaunchConfig,
+    pub knobs: KnobsConfig,
+    pub timecode: TimecodeConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DmxConfig {
+    /// Default destination for universes without one in the patch
+    pub dest: Option<IpAddr>,
+    /// UDP port to send to, if not the protocol's standard port
+    pub port: Option<u16>,
+    /// Protocol to send universes with
+    pub protocol: Option<Protocol>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MidiConfig {
+    /// Launchpad X port name, or a prefix or substring of it
+    pub pad: Option<String>,
+    /// Launch Control XL port name, or a prefix or substring of it
+    pub ctrl: Option<String>,
+    /// How hard a held Launchpad pad needs to be pressed to send aftertouch
+    pub aftertouch: Option<Aftertouch>,
+    /// How aftertouch maps onto duty and brightness, once past the threshold
+    pub aftertouch_curve: Option<AftertouchCurve>,
+    /// File to save MIDI learn bindings to
+    pub bindings: Option<PathBuf>,
+    /// Port to follow MIDI clock from, or `virtual`
+    pub clock_in: Option<String>,
+    /// Port to send MIDI clock to, or `virtual`
+    pub clock_out: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// WAV or FLAC file, capture device name, or `default`, to follow the beat of
+    pub source: Option<String>,
+    /// Minimum detection confidence, from `0..1`, before the beat is followed
+    pub confidence: Option<f64>,
+    /// Seconds to stop following the beat for after a tap-tempo
+    pub tap_override: Option<f64>,
+    /// Tempo range to detect, in BPM
+    pub min_bpm: Option<f64>,
+    pub max_bpm: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinkConfig {
+    /// Whether to join the Ableton Link session on the local network
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TapConfig {
+    /// Whether to round tapped tempos to a whole or half BPM
+    pub snap: Option<Snap>,
+    /// Number of beats to glide over to a tempo set by hand, rather than jumping
+    pub glide: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LaunchConfig {
+    /// Which boundary mode and palette changes wait for
+    pub quantize: Option<Quantize>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KnobsConfig {
+    /// Parameter for each knob in the row, left to right
+    pub send_a: Option<[Param; 8]>,
+    pub send_b: Option<[Param; 8]>,
+    pub pan: Option<[Param; 8]>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimecodeConfig {
+    /// Port to chase MIDI Time Code on, or `virtual`
+    pub mtc: Option<String>,
+    /// WAV or FLAC file, capture device name, or `default`, to chase Linear Timecode from
+    pub ltc: Option<String>,
+    /// Show file of cues to run along with the timecode
+    pub show: Option<PathBuf>,
+    /// Seconds to keep running for when timecode drops out
+    pub freewheel: Option<f64>,
+}
+
+impl Config {
+    /// Load a config file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+use crate::output::Output;
+
+/// UDP port E1.31 receivers listen on.
+pub const PORT: u16 = 5568;
+
+/// Size of the E1.31 header preceding the DMX start code.
+const HEADER: usize = 125;
+
+/// Minimal E1.31 (sACN) sender, able to address any universe.
+pub struct E131 {
+    socket: UdpSocket,
+    /// Destination UDP port
+    port: u16,
+    /// Component identifier, unique to this sender
+    cid: [u8; 16],
+    /// Next sequence number for each universe
+    seq: HashMap<u16, u8>,
+}
+
+impl E131 {
+    pub fn new(port: u16) -> Result<Self> {
+        Ok(Self { socket: UdpSocket::bind("0.0.0.0:0")?, port, cid: rand::random(), seq: HashMap::new() })
+    }
+}
+
+impl Output for E131 {
+    fn send(&mut self, addr: &IpAddr, universe: u16, dmx: &[u8]) -> Result<()> {
+        let seq = self.seq.entry(universe).or_default();
+        let packet = encode(&self.cid, *seq, universe, dmx);
+        *seq = seq.wrapping_add(1);
+
+        self.socket.send_to(&packet, SocketAddr::new(*addr, self.port))?;
+        Ok(())
+    }
+}
+
+/// Encode a single E1.31 data packet.
+pub fn encode(cid: &[u8; 16], seq: u8, universe: u16, dmx: &[u8]) -> Vec<u8> {
+    let len = HEADER + dmx.len();
+    // Each layer's length is counted from the start of that layer, with the high 4 bits set to 0x7 for the flags
+    let flags = |start: usize| (0x7000 | (len - start) as u16).to_be_bytes();
+
+    let mut p = Vec::with_capacity(len);
+
+    // Root layer
+    p.extend(0x0010u16.to_be_bytes()); // preamble size
+    p.extend(0x0000u16.to_be_bytes()); // postamble size
+    p.extend(b"ASC-E1.17\0\0\0");
+    p.extend(flags(16));
+    p.extend(0x0000_0004u32.to_be_bytes()); // VECTOR_ROOT_E131_DATA
+    p.extend(cid);
+
+    // Framing layer
+    p.extend(flags(38));
+    p.extend(0x0000_0002u32.to_be_bytes()); // VECTOR_E131_DATA_PACKET
+    let mut name = [0u8; 64];
+    name[..6].copy_from_slice(b"mslive");
+    p.extend(name);
+    p.push(100); // priority
+    p.extend(0u16.to_be_bytes()); // sync address
+    p.push(seq);
+    p.push(0); // options
+    p.extend(universe.to_be_bytes());
+
+    // DMP layer
+    p.extend(flags(115));
+    p.push(0x02); // VECTOR_DMP_SET_PROPERTY
+    p.push(0xa1); // address & data type
+    p.extend(0x0000u16.to_be_bytes()); // first property address
+    p.extend(0x0001u16.to_be_bytes()); // address increment
+    p.extend((dmx.len() as u16).to_be_bytes());
+    p.extend(dmx);
+
+    p
+}
@@ -0,0 +1,237 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use stagebridge::midi::device::{launch_control_xl::LaunchControlXL, launchpad_x::LaunchpadX};
+use stagebridge::midi::Midi;
+
+use crate::audio::{Audio, Estimate};
+use crate::clock::{ClockIn, ClockOut};
+use crate::learn::{Binding, Learn, Target};
+use crate::lights::Lights;
+use crate::link::Link;
+use crate::logic::{self, State};
+use crate::midi::Controller;
+use crate::output::Output;
+use crate::timecode::{Lock, Position, Show, Timecode, TimecodeIn};
+
+/// The lighting engine: input, logic, and DMX output, independent of the GUI.
+pub struct Engine {
+    state: State,
+    lights: Lights,
+    output: Box<dyn Output>,
+    pad: Controller<Midi<LaunchpadX>>,
+    ctrl: Controller<Midi<LaunchControlXL>>,
+    /// Any other controllers, bound with MIDI learn
+    learn: Learn,
+    tempo: TempoSync,
+    chase: Option<Chase>,
+}
+
+/// Optional ways to keep the beat in time with the outside world.
+#[derive(Default)]
+pub struct TempoSync {
+    /// Beat detected in the audio input
+    pub audio: Option<Audio>,
+    /// Incoming MIDI clock to follow
+    pub clock_in: Option<ClockIn>,
+    /// MIDI clock to send out
+    pub clock_out: Option<ClockOut>,
+    /// Tempo session on the local network
+    pub link: Option<Link>,
+}
+
+/// Timecode to lock to, and the show to run along with it.
+pub struct Chase {
+    input: TimecodeIn,
+    show: Show,
+    /// Where the timecode was last frame
+    position: Option<Position>,
+}
+
+impl Chase {
+    pub fn new(input: TimecodeIn, show: Show) -> Self {
+        Self { input, show, position: None }
+    }
+}
+
+/// A copy of the engine's state after a frame, for display.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub state: State,
+    pub lights: Lights,
+    /// Connected Launchpad port
+    pub pad: Option<String>,
+    /// Connected Launch Control port
+    pub ctrl: Option<String>,
+    /// Controls bound with MIDI learn
+    pub bindings: Vec<Binding>,
+    /// What the next control moved will be bound to, if learning
+    pub learning: Option<Target>,
+    /// Latest beat detected in the audio input
+    pub audio: Option<Estimate>,
+    /// Tempo of the incoming MIDI clock, if it's ticking
+    pub clock: Option<f64>,
+    /// Number of other peers in the tempo session, if joined
+    pub link: Option<usize>,
+    /// Last timecode received, and whether it's still arriving, if chasing
+    pub timecode: Option<(Timecode, Lock)>,
+}
+
+impl Engine {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: State,
+        lights: Lights,
+        output: Box<dyn Output>,
+        pad: Controller<Midi<LaunchpadX>>,
+        ctrl: Controller<Midi<LaunchControlXL>>,
+        learn: Learn,
+        tempo: TempoSync,
+        chase: Option<Chase>,
+    ) -> Self {
+        Self { state, lights, output, pad, ctrl, learn, tempo, chase }
+    }
+
+    /// Run a single frame, `dt` seconds after the last one.
+    pub fn frame(&mut self, dt: f64) {
+        let (s, l) = (&mut self.state, &mut self.lights);
+
+        // Controllers are optional, and may be plugged in or out at any time
+        self.pad.poll();
+        self.ctrl.poll();
+
+        if let Some(ctrl) = self.ctrl.get() {
+            for input in ctrl.recv() {
+                logic::on_ctrl(s, l, ctrl, input);
+            }
+        }
+        if let Some(pad) = self.pad.get() {
+            for input in pad.recv() {
+                logic::on_pad(s, l, pad, input);
+            }
+        }
+        // Leave the Launchpad and Launch Control to their own mappings, even before they connect,
+        // and clock and timecode to be followed
+        let devices: Vec<_> = [
+            Some(self.pad.name()),
+            self.pad.port(),
+            Some(self.ctrl.name()),
+            self.ctrl.port(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let skip: Vec<_> = [
+            self.tempo.clock_in.as_ref().and_then(|c| c.port()),
+            self.chase.as_ref().and_then(|c| c.input.port()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        for (target, val) in self.learn.recv(&skip, &devices) {
+            logic::on_learned(s, l, target, val);
+        }
+
+        logic::tick(dt, s, l);
+
+        // Timecode takes over the clock, so the show plays out the same every time
+        if let Some(chase) = &mut self.chase {
+            chase.position = chase.input.position();
+            if let Some(pos) = &chase.position {
+                if pos.jumped {
+                    log::info!("Chasing timecode from {:.2}s", pos.secs);
+                }
+                logic::on_timecode(s, l, pos.secs, chase.show.advance(pos));
+            }
+        }
+
+        // An incoming clock is the most precise, so it takes precedence over the audio input
+        let t = &mut self.tempo;
+        let mut lead = true;
+        match t.clock_in.as_ref().and_then(|c| Some((c.bpm()?, c.beat()))) {
+            Some((bpm, beat)) => logic::on_clock(s, bpm, beat),
+            None => match t.audio.as_ref().and_then(|a| Some((a.current()?, a.settings))) {
+                Some((est, settings)) => logic::on_audio(s, est, &settings),
+                None => lead = false,
+            },
+        }
+        // Otherwise keep in time with the rest of the session, sending it our own changes
+        if let Some(link) = &mut t.link {
+            link.sync(s, lead);
+        }
+        if let Some(out) = &mut t.clock_out {
+            out.update(s.bpm, s.phi_beat);
+        }
+
+        logic::render_lights(s, l);
+        if let Some(pad) = self.pad.get() {
+            logic::render_pad(s, pad);
+        }
+        match self.ctrl.get() {
+            Some(ctrl) => logic::render_ctrl(s, l, ctrl),
+            // It'll be cleared when plugged back in, so forget what was sent
+            None => s.ctrl_leds.clear(),
+        }
+
+        l.send(&mut *self.output);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            lights: self.lights.clone(),
+            pad: self.pad.port().map(String::from),
+            ctrl: self.ctrl.port().map(String::from),
+            bindings: self.learn.bindings().to_vec(),
+            learning: self.learn.learning(),
+            audio: self.tempo.audio.as_ref().and_then(Audio::latest),
+            clock: self.tempo.clock_in.as_ref().and_then(ClockIn::bpm),
+            link: self.tempo.link.as_ref().map(Link::peers),
+            timecode: self.chase.as_ref().and_then(|c| Some((c.input.latest()?, c.position?.lock))),
+        }
+    }
+
+    /// Send a single all-off frame.
+    pub fn blackout(&mut self) {
+        self.lights.blackout();
+        self.lights.send(&mut *self.output);
+    }
+
+    /// Run frames at a fixed `rate` in Hz until `running` is cleared, calling `f` after each one.
+    pub fn run(&mut self, rate: f64, running: &AtomicBool, mut f: impl FnMut(&Self)) {
+        let period = Duration::from_secs_f64(1.0 / rate);
+        let mut last = Instant::now();
+        let mut next = last + period;
+
+        while running.load(Ordering::Relaxed) {
+            let now = Instant::now();
+            self.frame((now - last).as_secs_f64());
+            last = now;
+
+            f(self);
+
+            // Sleep until the next frame is due. If we fell behind, don't try to catch up.
+            match next.checked_duration_since(Instant::now()) {
+                Some(wait) => thread::sleep(wait),
+                None => next = Instant::now(),
+            }
+            next += period;
+        }
+    }
+
+    /// Run the engine on its own thread at a fixed `rate` in Hz.
+    /// Returns the latest snapshot, updated after every frame.
+    pub fn spawn(mut self, rate: f64) -> Arc<Mutex<Snapshot>> {
+        let snapshot = Arc::new(Mutex::new(self.snapshot()));
+
+        let shared = Arc::clone(&snapshot);
+        thread::spawn(move || {
+            let running = AtomicBool::new(true);
+            self.run(rate, &running, |engine| *shared.lock().unwrap() = engine.snapshot());
+        });
+
+        snapshot
+    }
+}
@@ -1,10 +1,65 @@
 use stagebridge::color::{Rgb, Rgbw};
 use stagebridge::num::Interp;
+use std::sync::mpsc::Sender;
 
+use crate::engine::Snapshot;
+use crate::learn::{Request, Target};
 use crate::lights::Lights;
-use crate::logic::State;
+use crate::logic::{Multiplier, Param, State};
+use crate::timecode::Lock;
+
+pub fn render_gui(snap: &Snapshot, ctx: &egui::Context, learn: &Sender<Request>) {
+    let (s, l) = (&snap.state, &snap.lights);
+
+    egui::TopBottomPanel::top("status").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            status(ui, "Launchpad", &snap.pad);
+            ui.separator();
+            status(ui, "Launch Control", &snap.ctrl);
+            ui.separator();
+            ui.label(format!("Page: {}", s.layout.name(s.page)));
+            ui.separator();
+            ui.label(format!("BPM: {:.1}", s.bpm));
+            ui.label(format!("Phrase {}, bar {}, beat {}", s.phrases + 1, s.bars + 1, (s.phi_beat % 4.0) as usize + 1));
+            if s.mul != Multiplier::Normal {
+                ui.label(format!("(×{:.2})", s.mul.value()));
+            }
+            if s.nudge_back != s.nudge_fwd {
+                ui.label(if s.nudge_fwd { "(nudging forward)" } else { "(nudging back)" });
+            }
+            if s.tap.tapping(s.t) {
+                match s.tap.bpm() {
+                    Some(bpm) => ui.colored_label(egui::Color32::GREEN, format!("(tapped {bpm:.1}, {} taps)", s.tap.len())),
+                    None => ui.colored_label(egui::Color32::GREEN, "(tapping...)"),
+                };
+            }
+            if let Some(bpm) = snap.clock {
+                ui.label(format!("(MIDI clock {bpm:.1})"));
+            } else if let Some(est) = &snap.audio {
+                ui.label(format!("(audio {:.1}, {:.0}% confident)", est.bpm, est.confidence * 100.0));
+            }
+            if s.next_mode.is_some() || s.next_palette.is_some() {
+                ui.separator();
+                ui.colored_label(egui::Color32::YELLOW, format!("Launching on the next {:?}", s.quantize).to_lowercase());
+            }
+            if let Some(peers) = snap.link {
+                ui.separator();
+                ui.label(format!("Link: {peers} peers"));
+            }
+            if let Some((tc, lock)) = snap.timecode {
+                ui.separator();
+                match lock {
+                    Lock::Locked => ui.label(format!("Timecode {tc}")),
+                    Lock::Freewheel => ui.colored_label(egui::Color32::YELLOW, format!("Timecode {tc} (freewheeling)")),
+                };
+            }
+        });
+    });
+
+    egui::SidePanel::right("learn").show(ctx, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| render_learn(snap, ui, learn));
+    });
 
-pub fn render_gui(s: &State, l: &Lights, ctx: &egui::Context) {
     egui::CentralPanel::default().show(ctx, |ui| {
         let size = ui.available_size();
         let (resp, painter) = ui.allocate_painter(size, egui::Sense::hover());
@@ -12,6 +67,70 @@ pub fn render_gui(s: &State, l: &Lights, ctx: &egui::Context) {
     });
 }
 
+/// MIDI learn: pick something to bind, then move a control on any other controller.
+fn render_learn(snap: &Snapshot, ui: &mut egui::Ui, learn: &Sender<Request>) {
+    let s = &snap.state;
+    let request = |req| {
+        let _ = learn.send(req);
+    };
+
+    ui.heading("MIDI learn");
+    if let Some(target) = snap.learning {
+        ui.horizontal(|ui| {
+            ui.colored_label(egui::Color32::YELLOW, format!("Move a control to bind {}", describe(s, target)));
+            if ui.button("Cancel").clicked() {
+                request(Request::Cancel);
+            }
+        });
+    }
+    let mut learn_button = |ui: &mut egui::Ui, target| {
+        let selected = snap.learning == Some(target);
+        if ui.selectable_label(selected, describe(s, target)).clicked() {
+            request(if selected { Request::Cancel } else { Request::Learn(target) });
+        }
+    };
+
+    for page in 0..s.layout.pages() {
+        ui.collapsing(s.layout.name(page), |ui| {
+            for cell in s.layout.cells(page) {
+                learn_button(ui, Target::Pad { page, x: cell.x, y: cell.y });
+            }
+        });
+    }
+    ui.collapsing("Parameters", |ui| {
+        learn_button(ui, Target::Brightness);
+        for param in Param::ALL {
+            learn_button(ui, Target::Param(param));
+        }
+    });
+
+    ui.separator();
+    ui.heading("Bindings");
+    if snap.bindings.is_empty() {
+        ui.label("None yet");
+    }
+    for (i, binding) in snap.bindings.iter().enumerate() {
+        ui.horizontal(|ui| {
+            if ui.small_button("✖").on_hover_text("Unbind").clicked() {
+                request(Request::Unbind(i));
+            }
+            ui.label(format!("{}: {}", binding.control, describe(s, binding.target)));
+        });
+    }
+}
+
+/// Name something a control can be bound to.
+fn describe(s: &State, target: Target) -> String {
+    match target {
+        Target::Pad { page, x, y } => match s.layout.get(page, x, y) {
+            Some(cell) => format!("{} ({x}, {y}): {:?}", s.layout.name(page), cell.action),
+            None => format!("{} ({x}, {y}): nothing", s.layout.name(page)),
+        },
+        Target::Brightness => "Brightness".into(),
+        Target::Param(param) => format!("{param:?}"),
+    }
+}
+
 fn draw_inner(s: &State, l: &Lights, p: &egui::Painter, w0: f64, h0: f64) {
     // bounds
     let w = w0 * 0.8;
@@ -22,18 +141,27 @@ fn draw_inner(s: &State, l: &Lights, p: &egui::Painter, w0: f64, h0: f64) {
     // table
     rect(p, Rgbw::BLACK, x0 + w * 0.5, y0 + h * 0.8, 350.0, 75.0);
 
-    // pars
+    // pars: the outermost two hang on the sides, the rest along the top
     let dy = w / 9.0;
-    &l.pars[1..=8].feach(|i, fr, circ, par| {
-        circle(p, par.color, x0 + w * (1.0 - fr), y0, 10.0);
-    });
-    circle(p, l.pars[0].color, x0, y0 + dy, 10.0);
-    circle(p, l.pars[9].color, x0 + w, y0 + dy, 10.0);
+    match l.pars.len() {
+        0..=2 => l.pars.feach(|i, fr, circ, par| circle(p, par.color, x0 + w * (1.0 - fr), y0, 10.0)),
+        n => {
+            l.pars[1..n - 1].feach(|i, fr, circ, par| {
+                circle(p, par.color, x0 + w * (1.0 - fr), y0, 10.0);
+            });
+            circle(p, l.pars[0].color, x0, y0 + dy, 10.0);
+            circle(p, l.pars[n - 1].color, x0 + w, y0 + dy, 10.0);
+        }
+    }
 
     // bars
-    rect(p, l.bars[0].color.into(), x0 + w * 0.166, y0 + h * 0.93, 100.0, 15.0);
-    rect(p, l.bars[1].color.into(), x0 + w * 0.833, y0 + h * 0.93, 100.0, 15.0);
-    rect(p, l.strobe.color.into(), x0 + w * 0.5, y0 + h * 0.93, 80.0, 25.0);
+    l.bars.feach(|i, fr, circ, bar| {
+        rect(p, bar.color.into(), x0 + w * fr.lerp(0.166..0.833), y0 + h * 0.93, 100.0, 15.0);
+    });
+    l.strobes.feach(|i, fr, circ, strobe| {
+        let dx = (fr - 0.5) * 90.0 * (l.strobes.len() - 1) as f64;
+        rect(p, strobe.color.into(), x0 + w * 0.5 + dx, y0 + h * 0.93, 80.0, 25.0);
+    });
 
     // beams
     let bw = w * 0.7;
@@ -61,6 +189,14 @@ fn draw_inner(s: &State, l: &Lights, p: &egui::Painter, w0: f64, h0: f64) {
     })
 }
 
+/// Show whether a controller is connected.
+fn status(ui: &mut egui::Ui, name: &str, port: &Option<String>) {
+    match port {
+        Some(port) => ui.colored_label(egui::Color32::GREEN, format!("{name}: {port}")),
+        None => ui.colored_label(egui::Color32::RED, format!("{name}: disconnected")),
+    };
+}
+
 fn circle(p: &egui::Painter, c: Rgbw, x: f64, y: f64, r: f64) {
     p.circle_filled(egui::Pos2::new(x as f32, y as f32), r as f32, color(c));
 }
@@ -86,13 +222,13 @@ impl<T> Map<T> for [T] {
     fn fmap<F: FnMut(usize, f64, f64, &mut T)>(&mut self, mut f: F) {
         let n = self.len();
         for (i, t) in self.iter_mut().enumerate() {
-            f(i, i as f64 / (n - 1) as f64, i as f64 / n as f64, t);
+            f(i, i as f64 / (n - 1).max(1) as f64, i as f64 / n as f64, t);
         }
     }
     fn feach<F: FnMut(usize, f64, f64, &T)>(&self, mut f: F) {
         let n = self.len();
         for (i, t) in self.iter().enumerate() {
-            f(i, i as f64 / (n - 1) as f64, i as f64 / n as f64, t);
+            f(i, i as f64 / (n - 1).max(1) as f64, i as f64 / n as f64, t);
         }
     }
 }
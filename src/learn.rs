@@ -0,0 +1,375 @@
+use anyhow::{anyhow, Context, Result};
+use midir::{MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::logic::Param;
+use crate::midi;
+
+/// How often to check for MIDI ports being plugged in or unplugged.
+const POLL: Duration = Duration::from_secs(1);
+
+/// A kind of MIDI message a control sends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Message {
+    /// Note on and off, e.g. a key or pad
+    Note(u8),
+    /// Control change, e.g. a knob, fader, or button
+    Cc(u8),
+    PitchBend,
+}
+
+/// A control on some MIDI controller.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Control {
+    /// Full name of the port it's on
+    pub port: String,
+    /// MIDI channel, from `0..16`
+    pub channel: u8,
+    pub message: Message,
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Control { port, channel, message } = self;
+        match message {
+            Message::Note(note) => write!(f, "{port} ch{} note {note}", channel + 1),
+            Message::Cc(cc) => write!(f, "{port} ch{} CC {cc}", channel + 1),
+            Message::PitchBend => write!(f, "{port} ch{} pitch bend", channel + 1),
+        }
+    }
+}
+
+/// What a bound control does.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// Press a pad in the layout, whatever page it's on: a mode, palette, beat, tap, and so on
+    Pad { page: usize, x: i8, y: i8 },
+    /// Set the brightness, e.g. from a fader
+    Brightness,
+    /// Set a live parameter, like the Launch Control knobs do
+    Param(Param),
+}
+
+impl Target {
+    /// Whether the target is a button, rather than set anywhere in a range.
+    fn button(self) -> bool {
+        matches!(self, Target::Pad { .. })
+    }
+}
+
+/// A control, and what it's bound to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(flatten)]
+    pub control: Control,
+    pub target: Target,
+}
+
+/// A bindings file, e.g.
+///
+/// ```toml
+/// [[binding]]
+/// port = "APC40 mkII"
+/// channel = 0
+/// message = { note = 53 }
+/// target = { pad = { page = 0, x = 3, y = 2 } }
+///
+/// [[binding]]
+/// port = "APC40 mkII"
+/// channel = 0
+/// message = { cc = 14 }
+/// target = "brightness"
+/// ```
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BindingsFile {
+    #[serde(default)]
+    binding: Vec<Binding>,
+}
+
+/// Changes to the bindings, asked for from the GUI.
+#[derive(Clone, Debug)]
+pub enum Request {
+    /// Bind the next control that's moved to `Target`
+    Learn(Target),
+    Cancel,
+    /// Remove the binding at this index
+    Unbind(usize),
+}
+
+/// Listens on MIDI input ports for bound controls, and binds new ones when asked to.
+///
+/// Only ports with bindings are kept open, or every port while learning, so other apps can have the rest.
+pub struct Learn {
+    /// File the bindings are saved to
+    path: PathBuf,
+    bindings: Vec<Binding>,
+    /// Target to bind the next control that's moved to
+    learning: Option<Target>,
+    /// Bound buttons being held down
+    held: HashSet<Control>,
+
+    /// Open ports, and ones which failed to open so they aren't retried until plugged back in
+    conns: Vec<(String, MidiInputConnection<()>)>,
+    failed: Vec<String>,
+    /// When the ports were last checked
+    polled: Option<Instant>,
+
+    /// Messages received on any port, along with its name
+    tx: mpsc::Sender<(String, Vec<u8>)>,
+    rx: mpsc::Receiver<(String, Vec<u8>)>,
+    requests: (mpsc::Sender<Request>, mpsc::Receiver<Request>),
+}
+
+impl Learn {
+    /// Load bindings from `path`, or start without any if it doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file: BindingsFile = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Invalid bindings {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BindingsFile::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read bindings {}", path.display())),
+        };
+        if !file.binding.is_empty() {
+            log::info!("Loaded {} MIDI bindings from {}", file.binding.len(), path.display());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        Ok(Self {
+            path: path.to_owned(),
+            bindings: file.binding,
+            learning: None,
+            held: HashSet::new(),
+            conns: vec![],
+            failed: vec![],
+            polled: None,
+            tx,
+            rx,
+            requests: mpsc::channel(),
+        })
+    }
+
+    /// A way to ask for changes to the bindings from elsewhere, e.g. the GUI.
+    pub fn requests(&self) -> mpsc::Sender<Request> {
+        self.requests.0.clone()
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    /// Target waiting for a control to be moved, if any.
+    pub fn learning(&self) -> Option<Target> {
+        self.learning
+    }
+
+    /// Handle requests and incoming messages, returning what bound controls did since the last call.
+    ///
+    /// Buttons only show up when pressed, with their velocity, and released, with `0.0`.
+    /// Everything else shows up whenever it moves, from `0..1`. Ports in `skip` are left alone, along with
+    /// any port matching a controller in `devices` or on the same device as one, e.g. the Launchpad's DAW port.
+    pub fn recv(&mut self, skip: &[&str], devices: &[&str]) -> Vec<(Target, f64)> {
+        for request in self.requests.1.try_iter().collect::<Vec<_>>() {
+            match request {
+                Request::Learn(target) => {
+                    log::info!("Move a MIDI control to bind it to {target:?}");
+                    self.learning = Some(target);
+                    // Open every port right away
+                    self.polled = None;
+                }
+                Request::Cancel => self.learning = None,
+                Request::Unbind(i) if i < self.bindings.len() => {
+                    let binding = self.bindings.remove(i);
+                    log::info!("Unbound {} from {:?}", binding.control, binding.target);
+                    self.save();
+                }
+                Request::Unbind(_) => {}
+            }
+        }
+        self.poll(skip, devices);
+
+        let mut events = vec![];
+        for (port, msg) in self.rx.try_iter().collect::<Vec<_>>() {
+            let Some((channel, message, val)) = decode(&msg) else {
+                continue;
+            };
+            let control = Control { port, channel, message };
+
+            // The first control moved gets bound, except note offs from a key pressed before learning
+            if let Some(target) = self.learning {
+                if !matches!(message, Message::Note(_)) || val > 0.0 {
+                    self.learning = None;
+                    self.bind(control, target);
+                }
+                continue;
+            }
+
+            let Some(binding) = self.bindings.iter().find(|b| b.control == control) else {
+                continue;
+            };
+            let target = binding.target;
+            if !target.button() {
+                events.push((target, val));
+                continue;
+            }
+
+            // Only send presses and releases, e.g. not every step of a fader bound to a pad
+            let pressed = val > 0.0;
+            if pressed != self.held.contains(&control) {
+                match pressed {
+                    true => self.held.insert(control),
+                    false => self.held.remove(&control),
+                };
+                events.push((target, val));
+            }
+        }
+        events
+    }
+
+    /// Bind `control` to `target`, replacing whatever it was bound to before.
+    fn bind(&mut self, control: Control, target: Target) {
+        log::info!("Bound {control} to {target:?}");
+        self.bindings.retain(|b| b.control != control);
+        self.bindings.push(Binding { control, target });
+        self.save();
+    }
+
+    fn save(&self) {
+        let file = BindingsFile { binding: self.bindings.clone() };
+        let res = toml::to_string_pretty(&file)
+            .map_err(|e| anyhow!("{e}"))
+            .and_then(|text| Ok(std::fs::write(&self.path, text)?));
+        if let Err(e) = res {
+            log::error!("Failed to save MIDI bindings to {}: {e}", self.path.display());
+        }
+    }
+
+    /// Open ports which were plugged in or bound since the last check, and close ones which were unplugged or aren't needed.
+    fn poll(&mut self, skip: &[&str], devices: &[&str]) {
+        if self.polled.is_some_and(|t| t.elapsed() < POLL) {
+            return;
+        }
+        self.polled = Some(Instant::now());
+
+        let ports = match midi::ports() {
+            Ok(ports) => ports,
+            Err(e) => return log::debug!("Failed to list MIDI ports: {e}"),
+        };
+        let wanted = |port: &str| {
+            let skipped =
+                skip.contains(&port) || devices.iter().any(|d| midi::matches(port, d) || midi::device(port) == midi::device(d));
+            let bound = self.learning.is_some() || self.bindings.iter().any(|b| b.control.port == port);
+            bound && !skipped
+        };
+        self.conns.retain(|(port, _)| ports.contains(port) && wanted(port));
+        self.failed.retain(|port| ports.contains(port));
+
+        for port in ports {
+            let open = self.conns.iter().any(|(p, _)| *p == port);
+            if open || !wanted(&port) || self.failed.contains(&port) {
+                continue;
+            }
+            match self.open(&port) {
+                Ok(conn) => {
+                    log::debug!("Listening for MIDI learn on `{port}`");
+                    self.conns.push((port, conn));
+                }
+                Err(e) => {
+                    log::debug!("Failed to open MIDI port `{port}` for MIDI learn: {e}");
+                    self.failed.push(port);
+                }
+            }
+        }
+    }
+
+    fn open(&self, name: &str) -> Result<MidiInputConnection<()>> {
+        let input = MidiInput::new("mslive learn")?;
+        let ports = input.ports();
+        let port = ports
+            .iter()
+            .find(|p| input.port_name(p).is_ok_and(|n| n == name))
+            .ok_or_else(|| anyhow!("Port went away"))?;
+
+        let (tx, name) = (self.tx.clone(), name.to_owned());
+        let callback = move |_: u64, msg: &[u8], _: &mut ()| {
+            let _ = tx.send((name.clone(), msg.to_vec()));
+        };
+        input.connect(port, "mslive learn", callback, ()).map_err(|e| anyhow!("{e}"))
+    }
+}
+
+/// Decode a channel message into its channel, kind, and value from `0..1`. Note offs have a value of `0.0`.
+fn decode(msg: &[u8]) -> Option<(u8, Message, f64)> {
+    let &[status, data1, data2, ..] = msg else {
+        return None;
+    };
+    let channel = status & 0x0f;
+    match status & 0xf0 {
+        0x80 => Some((channel, Message::Note(data1), 0.0)),
+        0x90 => Some((channel, Message::Note(data1), data2 as f64 / 127.0)),
+        0xb0 => Some((channel, Message::Cc(data1), data2 as f64 / 127.0)),
+        0xe0 => Some((channel, Message::PitchBend, ((data2 as u16) << 7 | data1 as u16) as f64 / 16383.0)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_messages() {
+        // Note off either way, on channel 3
+        assert_eq!(decode(&[0x82, 60, 64]), Some((2, Message::Note(60), 0.0)));
+        assert_eq!(decode(&[0x92, 60, 0]), Some((2, Message::Note(60), 0.0)));
+        assert_eq!(decode(&[0x92, 60, 127]), Some((2, Message::Note(60), 1.0)));
+        assert_eq!(decode(&[0xb0, 14, 127]), Some((0, Message::Cc(14), 1.0)));
+
+        // Pitch bend is 14 bits, least significant first
+        assert_eq!(decode(&[0xef, 0x00, 0x00]), Some((15, Message::PitchBend, 0.0)));
+        assert_eq!(decode(&[0xef, 0x7f, 0x7f]), Some((15, Message::PitchBend, 1.0)));
+        assert_eq!(decode(&[0xe0, 0x00, 0x40]), Some((0, Message::PitchBend, 8192.0 / 16383.0)));
+
+        // Not channel messages, or cut short
+        assert_eq!(decode(&[0xf8]), None);
+        assert_eq!(decode(&[0xa0, 60, 10]), None);
+        assert_eq!(decode(&[0x90, 60]), None);
+    }
+
+    #[test]
+    fn presses_once() {
+        let mut learn = Learn::load("/nonexistent/bindings.toml").unwrap();
+        let pad = Target::Pad { page: 0, x: 1, y: 2 };
+        let control = |message| Control { port: "APC40 mkII".into(), channel: 0, message };
+        learn.bindings = vec![
+            Binding { control: control(Message::Note(53)), target: pad },
+            Binding { control: control(Message::Cc(7)), target: pad },
+            Binding { control: control(Message::Cc(14)), target: Target::Brightness },
+        ];
+        let mut recv = |msgs: &[&[u8]]| {
+            for msg in msgs {
+                learn.tx.send(("APC40 mkII".into(), msg.to_vec())).unwrap();
+            }
+            learn.recv(&[], &[])
+        };
+
+        // A key pressed, pressed again without a note off, then released
+        assert_eq!(recv(&[&[0x90, 53, 127], &[0x90, 53, 100]]), [(pad, 1.0)]);
+        assert_eq!(recv(&[&[0x80, 53, 0], &[0x90, 53, 0]]), [(pad, 0.0)]);
+
+        // A fader bound to a pad presses it once on the way up, and releases it at the bottom
+        let events = recv(&[&[0xb0, 7, 10], &[0xb0, 7, 64], &[0xb0, 7, 127], &[0xb0, 7, 0]]);
+        assert_eq!(events, [(pad, 10.0 / 127.0), (pad, 0.0)]);
+
+        // Anything else comes through on every move, and unbound controls not at all
+        let events = recv(&[&[0xb0, 14, 0], &[0xb0, 14, 127], &[0xb0, 15, 127], &[0x91, 53, 127]]);
+        assert_eq!(events, [(Target::Brightness, 0.0), (Target::Brightness, 1.0)]);
+    }
+}
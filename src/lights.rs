@@ -1,5 +1,5 @@
-use anyhow::Result;
 use std::net::IpAddr;
+use std::sync::Arc;
 
 use stagebridge::color::Rgbw;
 use stagebridge::dmx::device::bar_rgb_18w::Bar;
@@ -9,66 +9,94 @@ use stagebridge::dmx::device::par_rgbw_12x3w::Par;
 use stagebridge::dmx::device::spider_rgbw_8x10w::Spider;
 use stagebridge::dmx::device::strobe_rgb_35w::Strobe;
 use stagebridge::dmx::Device;
-use stagebridge::e131::E131;
 use stagebridge::prelude::*;
 
+use crate::output::Output;
+use crate::patch::{FixtureKind, Patch};
 use crate::utils::Pd;
 use crate::State;
 
+#[derive(Clone)]
 pub struct Lights {
-    e131: E131,
+    /// Destination for universes without one in the patch
     addr: IpAddr,
+    patch: Arc<Patch>,
 
-    pub pars: [Par; 10],
-    pub beams: [Beam; 4],
-    pub bars: [Bar; 2],
-    pub spiders: [Spider; 2],
-    pub strobe: Strobe,
-    pub laser: Laser,
+    pub pars: Vec<Par>,
+    pub beams: Vec<Beam>,
+    pub bars: Vec<Bar>,
+    pub spiders: Vec<Spider>,
+    pub strobes: Vec<Strobe>,
+    pub lasers: Vec<Laser>,
 }
 
 impl Lights {
-    pub fn new(addr: IpAddr) -> Result<Self> {
-        Ok(Self {
-            e131: E131::new()?,
+    pub fn new(addr: IpAddr, patch: Patch) -> Self {
+        // Allocate one light for each fixture of that type in the patch
+        fn alloc<T: Default>(patch: &Patch, kind: FixtureKind) -> Vec<T> {
+            std::iter::repeat_with(T::default).take(patch.count(kind)).collect()
+        }
+
+        Self {
             addr,
-            pars: Default::default(),
-            beams: Default::default(),
-            strobe: Default::default(),
-            bars: Default::default(),
-            spiders: Default::default(),
-            laser: Default::default(),
-        })
+            pars: alloc(&patch, FixtureKind::Par),
+            beams: alloc(&patch, FixtureKind::Beam),
+            bars: alloc(&patch, FixtureKind::Bar),
+            spiders: alloc(&patch, FixtureKind::Spider),
+            strobes: alloc(&patch, FixtureKind::Strobe),
+            lasers: alloc(&patch, FixtureKind::Laser),
+            patch: Arc::new(patch),
+        }
     }
 
     pub fn reset(&mut self) {
-        self.pars = Default::default();
-        self.beams = Default::default();
-        self.bars = Default::default();
-        self.spiders = Default::default();
-        self.strobe = Default::default();
-        // self.laser = Default::default();
+        self.pars.fill_with(Default::default);
+        self.beams.fill_with(Default::default);
+        self.bars.fill_with(Default::default);
+        self.spiders.fill_with(Default::default);
+        self.strobes.fill_with(Default::default);
+        // self.lasers.fill_with(Default::default);
     }
 
-    pub fn send(&mut self) {
-        let mut dmx = [0u8; 205];
+    /// Turn everything off, including the lasers.
+    pub fn blackout(&mut self) {
+        self.reset();
+        self.lasers.fill_with(Default::default);
+    }
 
-        for (i, par) in self.pars.iter().enumerate() {
-            par.encode(&mut dmx[1 + 8 * i..]);
-        }
-        for (i, beam) in self.beams.iter().enumerate() {
-            beam.encode(&mut dmx[81 + 15 * i..]);
-        }
-        for (i, bar) in self.bars.iter().enumerate() {
-            bar.encode(&mut dmx[149 + 7 * i..]);
-        }
-        for (i, spider) in self.spiders.iter().enumerate() {
-            spider.encode(&mut dmx[175 + 15 * i..]);
-        }
-        self.strobe.encode(&mut dmx[142..]);
-        self.laser.encode(&mut dmx[164..]);
+    /// Encode every patched universe into a DMX buffer, starting with the start code.
+    pub fn encode(&self) -> Vec<(u16, Vec<u8>)> {
+        self.patch
+            .universes()
+            .into_iter()
+            .map(|universe| {
+                let mut dmx = vec![0u8; self.patch.size(universe)];
+
+                for (i, fixture) in self.patch.iter().filter(|(_, f)| f.universe == universe) {
+                    let buf = &mut dmx[fixture.address..];
+                    match fixture.kind {
+                        FixtureKind::Par => self.pars[i].encode(buf),
+                        FixtureKind::Beam => self.beams[i].encode(buf),
+                        FixtureKind::Bar => self.bars[i].encode(buf),
+                        FixtureKind::Spider => self.spiders[i].encode(buf),
+                        FixtureKind::Strobe => self.strobes[i].encode(buf),
+                        FixtureKind::Laser => self.lasers[i].encode(buf),
+                    }
+                }
 
-        self.e131.send(&self.addr, &dmx);
+                (universe, dmx)
+            })
+            .collect()
+    }
+
+    /// Send every patched universe through `output`.
+    pub fn send(&self, output: &mut dyn Output) {
+        for (universe, dmx) in self.encode() {
+            let addr = self.patch.dest(universe).unwrap_or(self.addr);
+            if let Err(e) = output.send(&addr, universe, &dmx) {
+                log::debug!("Failed to send universe {universe} to {addr}: {e}");
+            }
+        }
     }
 }
 
@@ -82,7 +110,7 @@ impl Lights {
             spider.color1 = col1;
         });
         self.for_each_bar(|bar, i, fr| bar.color = col1.into());
-        self.strobe.color = col0.into();
+        self.for_each_strobe(|strobe, i, fr| strobe.color = col0.into());
     }
 
     /// Apply a function to the color of each light
@@ -94,7 +122,7 @@ impl Lights {
             spider.color1 = f(spider.color1);
         });
         self.for_each_bar(|bar, i, fr| bar.color = f(bar.color.into()).into());
-        self.strobe.color = f(self.strobe.color.into()).into();
+        self.for_each_strobe(|strobe, i, fr| strobe.color = f(strobe.color.into()).into());
     }
 
     // Iterate through the lights, with additional index and fr (from 0 to 1) parameters.
@@ -110,6 +138,12 @@ impl Lights {
     pub fn for_each_spider(&mut self, f: impl FnMut(&mut Spider, usize, f64)) {
         Self::for_each(&mut self.spiders, f);
     }
+    pub fn for_each_strobe(&mut self, f: impl FnMut(&mut Strobe, usize, f64)) {
+        Self::for_each(&mut self.strobes, f);
+    }
+    pub fn for_each_laser(&mut self, f: impl FnMut(&mut Laser, usize, f64)) {
+        Self::for_each(&mut self.lasers, f);
+    }
 
     fn for_each<T>(slice: &mut [T], mut f: impl FnMut(&mut T, usize, f64)) {
         let n = slice.len();
@@ -198,3 +232,71 @@ impl Lights {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode a fixture on its own, to compare against what lands in a universe.
+    fn encoded(kind: FixtureKind, fixture: &impl Device) -> Vec<u8> {
+        let mut buf = vec![0; kind.footprint()];
+        fixture.encode(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn encode_multiple_universes() {
+        let patch = Patch::parse(
+            r#"
+            [[universe]]
+            universe = 3
+            dest = "10.16.4.3"
+
+            [[fixture]]
+            name = "par0"
+            type = "par"
+            address = 1
+
+            [[fixture]]
+            name = "strobe"
+            type = "strobe"
+            universe = 2
+            address = 500
+
+            [[fixture]]
+            name = "par1"
+            type = "par"
+            universe = 2
+            address = 10
+            "#,
+        )
+        .unwrap();
+
+        let mut l = Lights::new([10, 16, 4, 1].into(), patch);
+        l.pars[0].color = Rgbw::RED;
+        l.pars[1].color = Rgbw::BLUE;
+        l.strobes[0].color = Rgb::WHITE;
+        let par0 = encoded(FixtureKind::Par, &l.pars[0]);
+        let par1 = encoded(FixtureKind::Par, &l.pars[1]);
+        let strobe = encoded(FixtureKind::Strobe, &l.strobes[0]);
+        assert_ne!(par0, par1);
+
+        // Universe 3 has a destination, but nothing patched in it
+        let dmx = l.encode();
+        assert_eq!(dmx.iter().map(|(u, _)| *u).collect::<Vec<_>>(), [1, 2]);
+
+        // Start code, then channels 1..=8
+        let u1 = &dmx[0].1;
+        assert_eq!(u1.len(), 1 + 8);
+        assert_eq!(u1[0], 0);
+        assert_eq!(u1[1..=8], par0);
+
+        // The second par, at channels 10..=17, up to the strobe at 500..=506
+        let u2 = &dmx[1].1;
+        assert_eq!(u2.len(), 1 + 506);
+        assert_eq!(u2[0], 0);
+        assert_eq!(u2[10..=17], par1);
+        assert_eq!(u2[500..=506], strobe);
+        assert!(u2[1..10].iter().chain(&u2[18..500]).all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,152 @@
+use rusty_link::{AblLink, SessionState};
+use std::time::{Duration, Instant};
+
+use crate::logic::State;
+
+/// Beats around `phi_beat`, so the whole 16 beat measure lines up with the session rather than just the bar.
+const QUANTUM: f64 = 16.0;
+/// How often to send our tempo and phase while following another source, so peers aren't thrashed.
+const LEAD: Duration = Duration::from_millis(250);
+/// How far the beat can stray from the session before it's sent out while following another source, in beats.
+const TOLERANCE: f64 = 0.02;
+
+/// A member of an Ableton Link session on the local network, e.g. with the DJ software.
+///
+/// Everyone in the session shares a tempo and beat timeline. Hand-made changes here, like a tap-tempo,
+/// are sent to the session, and otherwise we follow whatever the session does.
+pub struct Link {
+    link: AblLink,
+    session: SessionState,
+    /// Last hand-made change sent to the session, see `State::tapped`
+    tapped: Option<f64>,
+    /// When our tempo and phase were last sent while following another source
+    led: Option<Instant>,
+}
+
+impl Link {
+    /// Join or start a session at `bpm`. Link finds peers on every network interface, including loopback.
+    pub fn join(bpm: f64) -> Self {
+        let link = AblLink::new(bpm);
+        link.enable(true);
+        log::info!("Joined Link session");
+        Self { link, session: SessionState::new(), tapped: None, led: None }
+    }
+
+    /// Number of other peers in the session.
+    pub fn peers(&self) -> usize {
+        self.link.num_peers() as usize
+    }
+
+    /// Keep `bpm` and `phi_beat` in sync with the session.
+    ///
+    /// Changes made by hand since the last call, e.g. a tap-tempo or nudge, are sent out to the session.
+    /// If `lead`, we're following another source this frame, so our tempo and phase are sent out too.
+    /// The session has no notion of gliding, so tempo changes take effect right away.
+    pub fn sync(&mut self, s: &mut State, lead: bool) {
+        let now = self.link.clock_micros();
+        self.link.capture_app_session_state(&mut self.session);
+
+        let by_hand = (s.tapped.is_some() && s.tapped != self.tapped) || s.nudge_back != s.nudge_fwd;
+        self.tapped = s.tapped;
+
+        let bpm = s.glide.map_or(s.bpm, |g| g.to);
+        let drift = (s.phi_beat - self.session.phase_at_time(now, QUANTUM) + 8.0).rem_euclid(16.0) - 8.0;
+        let strayed = (bpm - self.session.tempo()).abs() > 1e-3 || drift.abs() > TOLERANCE;
+        let lead = lead && strayed && self.led.is_none_or(|t| t.elapsed() >= LEAD);
+
+        match by_hand || lead {
+            true => {
+                self.session.set_tempo(bpm, now);
+                self.session.force_beat_at_time(s.phi_beat, now as u64, QUANTUM);
+                self.link.commit_app_session_state(&self.session);
+                if lead {
+                    self.led = Some(Instant::now());
+                }
+                log::debug!("Sent bpm={bpm:.2} beat={:.2} to the Link session", s.phi_beat);
+            }
+            false => {
+                s.glide = None;
+                s.bpm = self.session.tempo();
+                s.shift(-drift);
+            }
+        }
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.link.enable(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::Glide;
+    use std::sync::Mutex;
+
+    /// Tests on one machine all end up in the same session, so take turns.
+    static SESSION: Mutex<()> = Mutex::new(());
+
+    /// Sync `s` with the session, until `done` or a few seconds have passed.
+    fn sync_until(link: &mut Link, s: &mut State, done: impl Fn(&Link, &State) -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            link.sync(s, false);
+            if done(link, s) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    /// Distance between two beats in the 16 beat measure.
+    fn dist(a: f64, b: f64) -> f64 {
+        let d = (a - b).rem_euclid(16.0);
+        d.min(16.0 - d)
+    }
+
+    #[test]
+    fn two_peers_over_loopback() {
+        let _session = SESSION.lock().unwrap();
+        let (mut a, mut b) = (Link::join(120.0), Link::join(120.0));
+        let (mut sa, mut sb) = (State::new(), State::new());
+        assert!(sync_until(&mut a, &mut sa, |a, _| a.peers() == 1), "Peers never found each other");
+
+        // A tap-tempo on one peer, right on a downbeat, reaches the other
+        sa.bpm = 128.0;
+        sa.phi_beat = 4.0;
+        sa.tapped = Some(sa.t);
+        a.sync(&mut sa, false);
+        assert!(sync_until(&mut b, &mut sb, |_, s| (s.bpm - 128.0).abs() < 1e-6), "Tempo never arrived");
+
+        // And both are on the same beat
+        let now = a.link.clock_micros();
+        a.sync(&mut sa, false);
+        b.sync(&mut sb, false);
+        let elapsed = (b.link.clock_micros() - now) as f64 / 1e6 * 128.0 / 60.0;
+        assert!(dist(sa.phi_beat + elapsed, sb.phi_beat) < 0.05, "{} and {}", sa.phi_beat, sb.phi_beat);
+    }
+
+    #[test]
+    fn glide_sent_once() {
+        let _session = SESSION.lock().unwrap();
+        let mut link = Link::join(120.0);
+        let mut s = State::new();
+        link.sync(&mut s, false);
+
+        // Tapped with a glide: the session goes straight to where it's headed
+        s.glide = Some(Glide { from: 120.0, to: 124.0, beats: 4.0, elapsed: 0.0 });
+        s.tapped = Some(1.0);
+        link.sync(&mut s, false);
+        assert_eq!(link.session.tempo(), 124.0);
+
+        // After which we follow the session, instead of sending every step of the glide
+        s.bpm = 121.0;
+        link.sync(&mut s, false);
+        assert_eq!(link.session.tempo(), 124.0);
+        assert_eq!(s.bpm, 124.0);
+        assert!(s.glide.is_none());
+    }
+}
@@ -5,6 +5,7 @@ use stagebridge::color::{Rgb, Rgbw};
 use stagebridge::dmx::device::beam_rgbw_60w::Beam;
 use stagebridge::dmx::device::laser_scan_30w::{Laser, LaserColor, LaserPattern};
 use stagebridge::dmx::device::spider_rgbw_8x10w::Spider;
+use std::sync::Arc;
 use std::time::Instant;
 use std::{thread, time::Duration};
 
@@ -16,8 +17,12 @@ use stagebridge::midi::device::{
 use stagebridge::midi::Midi;
 use stagebridge::prelude::*;
 
+use crate::audio::{self, Estimate};
+use crate::learn::Target;
 use crate::lights::Lights;
-use crate::utils::{Hold, Pd};
+use crate::tap::TapTempo;
+use crate::timecode::Cue;
+use crate::utils::{de_color, de_range, HoldStack, Pd};
 
 ///////////////////////// TODO /////////////////////////
 
@@ -35,7 +40,7 @@ use crate::utils::{Hold, Pd};
 
 ///////////////////////// STATE /////////////////////////
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct State {
     /// Time since the last `tick()` in seconds
     pub dt: f64,
@@ -44,23 +49,77 @@ pub struct State {
 
     /// Current approximately matched BPM
     pub bpm: f64,
-    /// Timestamps when the beatmatch button was tapped
-    pub bpm_taps: Vec<f64>,
-    /// Current fractional beat number in a 16 beat measure at the current `bpm`. Ranges from `0..16` and wraps around
+    /// Taps on the beatmatch button
+    pub tap: TapTempo,
+    /// When the tempo or phase was last set by hand, which takes precedence over the audio input for a while
+    pub tapped: Option<f64>,
+    /// Whether the nudge back/forward buttons are held, temporarily slowing down or speeding up `phi`
+    pub nudge_back: bool,
+    pub nudge_fwd: bool,
+    /// Current fractional beat number in a 16 beat measure at the current `bpm` and `mul`. Ranges from `0..16` and wraps around
     pub phi: f64,
-    /// Bpm multiplier, e.g. 0.5 for half-time, 2.0 for double-time.
-    pub phi_mul: f64,
+    /// Current fractional beat number in a 16 beat measure of the music itself, ignoring `mul`. Ranges from `0..16`.
+    /// Tempo sync keeps this in time with the outside world, and `phi` with it.
+    pub phi_beat: f64,
+    /// Latched bpm multiplier for `phi`, e.g. half-time or double-time
+    pub mul: Multiplier,
+    /// Tempo change in progress
+    pub glide: Option<Glide>,
+    /// Number of beats to glide over when the tempo is changed by hand
+    pub glide_beats: f64,
+
+    /// Beats since startup, like `phi_beat` but without wrapping around
+    pub beat_abs: f64,
+    /// Furthest `beat_abs` has reached, so boundaries pulled back over by tempo sync aren't counted again
+    beat_max: f64,
+    /// Bars and phrases (16 beats, once around `phi_beat`) since startup
+    pub bars: u64,
+    pub phrases: u64,
 
     /// Color palette
     pub palette: Palette,
     /// Lighting mode
     pub mode: Mode,
+    /// Which boundary mode and palette changes wait for
+    pub quantize: Quantize,
+    /// Mode and palette changes waiting for the next boundary
+    pub next_mode: Option<Launch<Mode>>,
+    pub next_palette: Option<Launch<Palette>>,
     /// Manual beat
     pub beat: Option<ManualBeat>,
+    /// What each pad does
+    pub layout: Arc<Layout>,
+    /// Layout page shown on the pad
+    pub page: usize,
+    /// Spider movement picked on the pad, instead of the mode's own
+    pub spider: Option<SpiderPattern>,
+    /// Strobe duty from aftertouch on a held strobe pad, instead of the mode's own
+    pub duty: Option<f64>,
+    /// How aftertouch maps onto duty and brightness
+    pub aftertouch_curve: AftertouchCurve,
+    /// Momentary layers over the mode while pads are held
+    pub holds: Holds,
+    /// Launchpad pads held down, and the page each was pressed on
+    pub pad_down: Vec<(i8, i8, usize)>,
 
     /// Global brightness modifier
     pub brightness: f64,
 
+    /// Launch Control knob mapping
+    pub knobs: Knobs,
+    /// Offsets added to every beam's pitch and yaw
+    pub beam_pitch: f64,
+    pub beam_yaw: f64,
+    /// Spider movement speed, as a power of 2
+    pub spider_speed: i8,
+    /// Palette hue rotation, in turns around the color wheel
+    pub hue: f64,
+    pub laser_size: f64,
+    pub laser_rotate: f64,
+
+    /// LEDs last sent to the Launch Control, so only changes are sent
+    pub ctrl_leds: Vec<CtrlLed>,
+
     /// Pad debug mode. Enable for colored button guide, disable for pretty pad effects.
     pub debug: bool,
 
@@ -82,9 +141,9 @@ impl State {
         Self {
             debug: true,
             brightness: 0.25,
+            laser_size: 0.75,
             palette: Palette::Rainbow,
             bpm: 120.0,
-            phi_mul: 1.0,
             ..Default::default()
         }
     }
@@ -99,9 +158,128 @@ impl State {
     fn dt(&self, n: usize, d: usize) -> f64 {
         self.dt / ((self.bpm / 60.0) * Pd(n, d).fr())
     }
+
+    /// Shift the beat by `beats`, moving `phi` along with it.
+    ///
+    /// Bars and phrases moved forward over are counted, and launch queued changes on the chosen boundary.
+    pub fn shift(&mut self, beats: f64) {
+        self.beat_abs += beats;
+        self.count();
+        self.phi_beat = (self.phi_beat + beats).rem_euclid(16.0);
+        self.phi = (self.phi + beats * self.mul.value()).rem_euclid(16.0);
+    }
+
+    /// Reset the beat to the start of a phrase, counting it if it's a new one.
+    pub fn restart_phrase(&mut self) {
+        self.beat_abs = (self.beat_abs / 16.0).round() * 16.0;
+        self.count();
+        self.phi_beat = 0.0;
+        self.phi = 0.0;
+    }
+
+    /// Count boundaries passed for the first time, launching queued changes on the chosen one.
+    fn count(&mut self) {
+        let (from, to) = (self.beat_max, self.beat_max.max(self.beat_abs));
+        self.beat_max = to;
+        self.bars = (to / 4.0).floor() as u64;
+        self.phrases = (to / 16.0).floor() as u64;
+        if self.quantize.beats().is_some_and(|len| (to / len).floor() > (from / len).floor()) {
+            launch(self);
+        }
+    }
+}
+
+/// Bpm multiplier for `phi`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Multiplier {
+    Quarter,
+    Half,
+    Triplet,
+    #[default]
+    Normal,
+    Double,
+    Quad,
+}
+
+impl Multiplier {
+    pub fn value(self) -> f64 {
+        match self {
+            Multiplier::Quarter => 0.25,
+            Multiplier::Half => 0.5,
+            Multiplier::Triplet => 2.0 / 3.0,
+            Multiplier::Normal => 1.0,
+            Multiplier::Double => 2.0,
+            Multiplier::Quad => 4.0,
+        }
+    }
+
+    /// One step faster, up to ×4. Triplets go back to normal.
+    pub fn faster(self) -> Self {
+        match self {
+            Multiplier::Quarter => Multiplier::Half,
+            Multiplier::Half | Multiplier::Triplet => Multiplier::Normal,
+            Multiplier::Normal => Multiplier::Double,
+            Multiplier::Double | Multiplier::Quad => Multiplier::Quad,
+        }
+    }
+
+    /// One step slower, down to ×1/4. Triplets go to half-time.
+    pub fn slower(self) -> Self {
+        match self {
+            Multiplier::Quad => Multiplier::Double,
+            Multiplier::Double => Multiplier::Normal,
+            Multiplier::Normal | Multiplier::Triplet => Multiplier::Half,
+            Multiplier::Half | Multiplier::Quarter => Multiplier::Quarter,
+        }
+    }
+}
+
+/// Which boundary to wait for before launching a mode or palette change.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Quantize {
+    /// Change right away
+    #[default]
+    Off,
+    Beat,
+    Bar,
+    /// Every 16 beats
+    Phrase,
+}
+
+impl Quantize {
+    /// Length of the boundary in beats.
+    pub fn beats(self) -> Option<f64> {
+        match self {
+            Quantize::Off => None,
+            Quantize::Beat => Some(1.0),
+            Quantize::Bar => Some(4.0),
+            Quantize::Phrase => Some(16.0),
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+/// A change waiting to launch, along with the pad button that queued it.
+#[derive(Clone, Copy, Debug)]
+pub struct Launch<T> {
+    pub val: T,
+    pub page: usize,
+    pub x: i8,
+    pub y: i8,
+}
+
+/// A gradual tempo change, so everything speeds up or slows down smoothly instead of jumping.
+#[derive(Clone, Copy, Debug)]
+pub struct Glide {
+    pub from: f64,
+    pub to: f64,
+    /// Length of the glide, and how far along it is, in beats
+    pub beats: f64,
+    pub elapsed: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Mode {
     /// All off
     #[default]
@@ -115,6 +293,7 @@ pub enum Mode {
         /// How often to flash
         pd: Pd,
         /// Brightness range for each flash, from 0..1
+        #[serde(deserialize_with = "de_range")]
         r: Range,
         beam: BeamPattern,
     },
@@ -158,7 +337,8 @@ pub enum Mode {
 
 ///////////////////////// COLOR PALETTE /////////////////////////
 
-#[derive(Clone, Copy, Debug, Default)]
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Palette {
     /// Gradually cycling rainbow
     #[default]
@@ -166,13 +346,13 @@ pub enum Palette {
     RgbOsc,
     RainbowOsc,
     /// Solid color
-    Solid(Rgbw),
-    Split(Rgbw, Rgbw),
+    Solid(#[serde(deserialize_with = "de_color")] Rgbw),
+    Split(#[serde(deserialize_with = "de_color")] Rgbw, #[serde(deserialize_with = "de_color")] Rgbw),
 }
 
 impl Palette {
     fn color0(self, s: &mut State, fr: f64) -> Rgbw {
-        match self {
+        let col = match self {
             Palette::Rainbow => Rgb::hsv(s.phi(16, 1), 1.0, 1.0).into(),
             Palette::RgbOsc => match s.pd(Pd(1, 2)).ramp(1.0) {
                 ..0.33 => Rgbw::RED,
@@ -191,17 +371,32 @@ impl Palette {
             },
             Palette::Solid(col) => col,
             Palette::Split(col0, _col1) => col0,
-        }
+        };
+        shift_hue(col, s.hue)
     }
 
     fn color1(self, s: &mut State, fr: f64) -> Rgbw {
         match self {
-            Palette::Split(_col0, col1) => col1,
+            Palette::Split(_col0, col1) => shift_hue(col1, s.hue),
             _ => self.color0(s, fr),
         }
     }
 }
 
+/// Rotate the hue of `col` by `turns` around the color wheel. Whites and grays stay as they are.
+fn shift_hue(col: Rgbw, turns: f64) -> Rgbw {
+    if turns == 0.0 {
+        return col;
+    }
+    let Rgbw(r, g, b, w) = col;
+    // Rotate around the gray axis of the RGB cube
+    let (sin, cos) = (turns * std::f64::consts::TAU).sin_cos();
+    let (k, q) = ((1.0 - cos) / 3.0, sin / 3f64.sqrt());
+    let (c0, c1, c2) = (cos + k, k - q, k + q);
+    let f = |x: f64| x.clamp(0.0, 1.0);
+    Rgbw(f(c0 * r + c1 * g + c2 * b), f(c2 * r + c0 * g + c1 * b), f(c1 * r + c2 * g + c0 * b), w)
+}
+
 ///////////////////////// WHIRL ////////////////////////
 
 enum WhirlState {
@@ -270,7 +465,8 @@ impl WhirlState {
 
 ///////////////////////// BEAM PATTERNS /////////////////////////
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum BeamPattern {
     Down,
     Out,
@@ -297,66 +493,54 @@ pub enum BeamPattern {
 }
 
 impl BeamPattern {
-    fn apply(self, s: &mut State, pd: Pd, beam: &mut Beam, i: usize, fr: f64) {
-        let (pitch, yaw) = self.angles(s, pd, i, fr);
+    fn apply(self, s: &mut State, pd: Pd, beam: &mut Beam, i: usize, n: usize) {
+        let (pitch, yaw) = self.angles(s, pd, i, n);
         beam.pitch = pitch;
         beam.yaw = yaw;
     }
 
-    /// Calculate (pitch, yaw) for the given pattern
-    fn angles(self, s: &mut State, pd: Pd, i: usize, fr: f64) -> (f64, f64) {
+    /// Calculate (pitch, yaw) for beam `i` of `n` in the given pattern
+    fn angles(self, s: &mut State, pd: Pd, i: usize, n: usize) -> (f64, f64) {
+        let fr = i as f64 / n as f64;
+        // Position from -1 (first beam) to 1 (last beam)
+        let side = spread(i, n);
+
         match self {
             BeamPattern::Down => (0.0, 0.0),
             BeamPattern::Out => (0.5, 0.33),
             BeamPattern::Center => (
                 0.85,
                 match i {
+                    // The first beam is flipped around to reach the center
                     0 => 0.05,
-                    1 => 0.7,
-                    2 => 0.63,
-                    _ => 0.6,
+                    _ => table(&[0.7, 0.63, 0.6], i - 1, n - 1),
                 },
             ),
-            BeamPattern::SpreadOut => (
-                0.0,
-                match i {
-                    0 => 0.5 - 0.05,
-                    1 => 0.5 - 0.02,
-                    2 => 0.5 + 0.02,
-                    _ => 0.5 + 0.05,
-                } - (0.25 / 1.5),
-            ),
-            BeamPattern::SpreadIn => (
-                0.0,
-                match i {
-                    0 => 0.5 + 0.09,
-                    1 => 0.5 + 0.07,
-                    2 => 0.5 - 0.07,
-                    _ => 0.5 - 0.09,
-                } - (0.25 / 1.5),
-            ),
+            BeamPattern::SpreadOut => (0.0, 0.5 + table(&[-0.05, -0.02, 0.02, 0.05], i, n) - (0.25 / 1.5)),
+            BeamPattern::SpreadIn => (0.0, 0.5 + table(&[0.09, 0.07, -0.07, -0.09], i, n) - (0.25 / 1.5)),
             BeamPattern::Cross { pitch, angle, fanning } => {
                 let a = angle.unwrap_or(0.13);
-                let f = if i == 1 || i == 2 { fanning.unwrap_or(1.0) } else { 1. };
+                // Fan out the inner beams
+                let f = if side.abs() < 1.0 { fanning.unwrap_or(1.0) } else { 1. };
                 (
                     pitch * f,
-                    match i {
-                        0 => 0.5 + a,
-                        1 => 0.5 + a,
-                        2 => 0.5 - a,
-                        _ => 0.5 - a,
+                    match side {
+                        _ if side < 0.0 => 0.5 + a,
+                        _ if side > 0.0 => 0.5 - a,
+                        _ => 0.5,
+                    } - (0.25 / 1.5),
+                )
+            }
+            BeamPattern::CrissCross { pitch } => {
+                let a = table(&[0.08, 0.05, 0.05, 0.08], i, n);
+                (
+                    pitch,
+                    match i % 2 == 0 {
+                        true => 0.5 + a,
+                        false => 0.5 - a,
                     } - (0.25 / 1.5),
                 )
             }
-            BeamPattern::CrissCross { pitch } => (
-                pitch,
-                match i {
-                    0 => 0.5 + 0.08,
-                    1 => 0.5 - 0.05,
-                    2 => 0.5 + 0.05,
-                    _ => 0.5 - 0.08,
-                } - (0.25 / 1.5),
-            ),
             BeamPattern::SnapY => {
                 let t = s.pd(pd.mul(4)).square(1.0, 0.5);
                 let pitch = 0.3
@@ -371,7 +555,7 @@ impl BeamPattern {
                 let pitch = 0.3 * s.pd(pd.mul(2)).square(1.0, 0.5);
                 let yaw = 0.5
                     + 0.13
-                        * match i > 1 {
+                        * match side > 0.0 {
                             true => t,
                             false => -t,
                         };
@@ -451,6 +635,26 @@ impl BeamPattern {
     }
 }
 
+/// Position of fixture `i` of `n`, from -1 (first) to 1 (last).
+fn spread(i: usize, n: usize) -> f64 {
+    match n {
+        0 | 1 => 0.0,
+        _ => (i as f64 / (n - 1) as f64) * 2.0 - 1.0,
+    }
+}
+
+/// Look up the value for fixture `i` of `n` in a table tuned for `values.len()` fixtures,
+/// interpolating between entries when the counts differ.
+fn table(values: &[f64], i: usize, n: usize) -> f64 {
+    let last = values.len() - 1;
+    let x = (spread(i, n) + 1.0) * 0.5 * last as f64;
+    let j = (x.floor() as usize).min(last);
+    match values.get(j + 1) {
+        Some(&v) => (x - j as f64).lerp(values[j]..v),
+        None => values[j],
+    }
+}
+
 ///////////////////////// MANUAL BEAT /////////////////////////
 
 #[derive(Clone, Copy)]
@@ -465,8 +669,82 @@ pub struct ManualBeat {
     /// Duration of right beat.
     pd1: Pd,
 
-    /// Brightness range of flash.
-    r: Range,
+    /// Brightness range of left and right flashes, so each side flashes as hard as it was hit.
+    r0: Range,
+    r1: Range,
+}
+
+///////////////////////// HOLDS /////////////////////////
+
+/// A momentary override, layered over the mode for as long as its pad is held.
+#[derive(Clone, Copy, Debug)]
+pub enum Layer {
+    /// Everything in one color
+    Color(Rgbw),
+    /// Everything off, except the lasers
+    Blackout,
+    /// Strobe whatever's on
+    Strobe(Pd),
+    /// Only the beams, moving in a pattern
+    Beams { pd: Pd, beam: BeamPattern },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Dimmer {
+    Blackout,
+    Strobe(Pd),
+}
+
+/// Layers being held. Each part can be held by several pads at once, with the latest press on top.
+#[derive(Clone, Debug, Default)]
+pub struct Holds {
+    pub color: HoldStack<Rgbw>,
+    pub dimmer: HoldStack<Dimmer>,
+    pub beams: HoldStack<(Pd, BeamPattern)>,
+}
+
+impl Holds {
+    pub fn press(&mut self, page: usize, x: i8, y: i8, layer: Layer) {
+        match layer {
+            Layer::Color(col) => self.color.hold(page, x, y, col),
+            Layer::Blackout => self.dimmer.hold(page, x, y, Dimmer::Blackout),
+            Layer::Strobe(pd) => self.dimmer.hold(page, x, y, Dimmer::Strobe(pd)),
+            Layer::Beams { pd, beam } => self.beams.hold(page, x, y, (pd, beam)),
+        }
+    }
+
+    /// Let go of anything held by the pad at `x`, `y` on `page`.
+    pub fn release(&mut self, page: usize, x: i8, y: i8) {
+        self.color.release(page, x, y);
+        self.dimmer.release(page, x, y);
+        self.beams.release(page, x, y);
+    }
+}
+
+/// Layer the holds over whatever the mode rendered.
+fn render_holds(s: &mut State, l: &mut Lights) {
+    if let Some(&col) = s.holds.color.value() {
+        l.map_colors(|_| col);
+    }
+    if let Some(&(pd, beam)) = s.holds.beams.value() {
+        let col = match s.holds.color.value() {
+            Some(&col) => col,
+            None => s.palette.color0(s, 0.0),
+        };
+        l.map_colors(|_| Rgbw::BLACK);
+        let n = l.beams.len();
+        l.for_each_beam(|b, i, fr| {
+            beam.apply(s, pd, b, i, n);
+            b.color = col;
+        });
+    }
+    if let Some(&dimmer) = s.holds.dimmer.value() {
+        let env = match dimmer {
+            Dimmer::Blackout => 0.0,
+            Dimmer::Strobe(pd) => s.pd(pd.mul(2)).square(1.0, 0.5),
+        };
+        l.map_colors(|c| c * env);
+    }
 }
 
 ///////////////////////// SPIDER PATTERNS /////////////////////////
@@ -489,27 +767,28 @@ impl SpiderPattern {
 
     /// Calculate (pos0, pos1) for the given pattern
     fn pos(self, s: &mut State, i: usize, fr: f64) -> (f64, f64) {
+        let speed = s.spider_speed;
         match self {
             SpiderPattern::Up => (0.0, 0.52),
             SpiderPattern::Down => (0.67, 0.52),
             SpiderPattern::Wave { pd } => {
-                let fr = s.pd(pd.mul(2)).tri(1.0);
+                let fr = s.pd(pd.mul(2).speed(speed)).tri(1.0);
                 (fr, 1.0 - fr)
             }
             SpiderPattern::Alternate { pd } => {
-                let t = s.pd(pd.mul(2));
-                let t = match i {
-                    0 => t,
-                    _ => t.phase(1.0, 0.5),
+                let t = s.pd(pd.mul(2).speed(speed));
+                let t = match i % 2 == 0 {
+                    true => t,
+                    false => t.phase(1.0, 0.5),
                 };
                 let fr = t.tri(1.0);
                 (fr, fr)
             }
             SpiderPattern::Snap { pd } => {
-                let t = s.pd(pd.mul(2));
-                let t = match i {
-                    0 => t,
-                    _ => t.phase(1.0, 0.5),
+                let t = s.pd(pd.mul(2).speed(speed));
+                let t = match i % 2 == 0 {
+                    true => t,
+                    false => t.phase(1.0, 0.5),
                 };
                 let fr = t.square(1.0, 0.5);
                 (fr, fr)
@@ -577,8 +856,9 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
 
             if let Some(beams) = beams {
                 let col = s.palette.color1(s, 0.0);
+                let n = l.beams.len();
                 l.for_each_beam(|beam, i, fr| {
-                    beams.apply(s, Pd(4, 1), beam, i, fr);
+                    beams.apply(s, Pd(4, 1), beam, i, n);
                     beam.color = col;
                 });
             }
@@ -590,9 +870,10 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
 
             l.split(s.palette.color0(s, 0.0) * env, s.palette.color1(s, 0.0) * env);
 
+            let n = l.beams.len();
             l.for_each_beam(|beam, i, fr| {
                 // let pd_min
-                beam_pattern.apply(s, pd, beam, i, fr);
+                beam_pattern.apply(s, pd, beam, i, n);
                 let beam_env = match beam_pattern {
                     BeamPattern::Whirl => {
                         let angle = (s.pd(pd) + fr * 1.5) % 1.0;
@@ -606,6 +887,7 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
         }
         Mode::Strobe { pd, duty } => {
             let p = s.palette;
+            let duty = s.duty.unwrap_or(duty);
 
             let env = s.pd(pd.mul(2)).square(1.0, duty.in_exp().lerp(1.0..0.5));
 
@@ -626,33 +908,39 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
             // l.for_each_beam(|beam, i, fr| BeamPattern::Square { pd }.apply(s, beam, i, fr));
             // l.for_each_spider(|spider, i, fr| SpiderPattern::Alternate { pd }.apply(s, spider, i, fr));
 
-            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, fr));
+            let n = l.beams.len();
+            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, n));
             l.for_each_spider(|spider, i, fr| SpiderPattern::Alternate { pd: Pd(2, 1) }.apply(s, spider, i, fr));
-            l.strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env);
+            l.for_each_strobe(|strobe, i, fr| strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env));
         }
         Mode::Strobe0 { pd, duty } => {
             let p = s.palette;
+            let duty = s.duty.unwrap_or(duty);
             let env = s.pd(pd.mul(2)).square(1.0, duty.in_exp().lerp(1.0..0.5));
             l.split(s.palette.color0(s, 0.0) * env, Rgbw::BLACK);
 
-            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, fr));
+            let n = l.beams.len();
+            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, n));
             l.for_each_spider(|spider, i, fr| SpiderPattern::Alternate { pd: Pd(2, 1) }.apply(s, spider, i, fr));
-            l.strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env);
+            l.for_each_strobe(|strobe, i, fr| strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env));
         }
         Mode::Strobe1 { pd, duty } => {
             let p = s.palette;
+            let duty = s.duty.unwrap_or(duty);
             let env = s.pd(pd.mul(2)).square(1.0, duty.in_exp().lerp(1.0..0.5));
             l.split(Rgbw::BLACK, s.palette.color0(s, 0.0) * env);
 
-            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, fr));
+            let n = l.beams.len();
+            l.for_each_beam(|beam, i, fr| BeamPattern::Square.apply(s, Pd(2, 1), beam, i, n));
             l.for_each_spider(|spider, i, fr| SpiderPattern::Alternate { pd: Pd(2, 1) }.apply(s, spider, i, fr));
-            l.strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env);
+            l.for_each_strobe(|strobe, i, fr| strobe.color = Rgb::from(s.palette.color0(s, 0.0) * env));
         }
         Mode::Whirl { pd } => {
             // let p = s.palette;
             let col = s.palette.color0(s, 0.0);
             // l.map_colors(|_| s.palette.color0(s, 0.0));
-            l.for_each_beam(|beam, i, fr| BeamPattern::Whirl.apply(s, pd, beam, i, fr));
+            let n = l.beams.len();
+            l.for_each_beam(|beam, i, fr| BeamPattern::Whirl.apply(s, pd, beam, i, n));
             l.for_each_beam(|beam, i, fr| {
                 let angle = (s.pd(pd) + fr * 1.5) % 1.0;
                 let warmup = 0.1;
@@ -672,9 +960,10 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
         }
         Mode::Chase { pd, beam: beam_pattern } => {
             l.for_each_par(|par, i, fr| par.color = Rgbw::WHITE * s.pd(pd.mul(4)).phase(1.0, fr).square(1.0, 0.1));
+            let n = l.beams.len();
             l.for_each_beam(|beam, i, fr| {
                 beam.color = Rgbw::WHITE * s.pd(pd.mul(4)).phase(1.0, fr).square(1.0, 0.1);
-                beam_pattern.apply(s, Pd(1, 2), beam, i, fr);
+                beam_pattern.apply(s, Pd(1, 2), beam, i, n);
             });
         }
         Mode::ChaseNotColorful { pd } => {
@@ -683,8 +972,9 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
             // l.for_each_par(|par, i, fr| {
             //     par.color = Rgbw::WHITE * s.phi.fmod_div(pd.mul(4).fr() + fr * 4.3).phase(1.0, fr).square(1.0, 0.3);
             // });
+            let n = l.beams.len();
             l.for_each_beam(|beam, i, fr| {
-                let offset = if i < 2 { 0.0 } else { 0.5 };
+                let offset = if fr < 0.5 { 0.0 } else { 0.5 };
                 beam.color = col0 * s.pd(pd).phase(1.0, offset).square(1.0, 0.33);
                 // let base = if i % 2 == 0 { col0 } else { col1 };
                 // beam.color = Rgbw::WHITE * s.pd(pd.mul(4)).phase(1.0, fr).square(1.0, 1.0 / (10. + fr * 20.));
@@ -693,15 +983,16 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
                     angle: Some(s.pd(pd.mul(8)).fsin(1.) * 0.2 - 0.1),
                     fanning: Some(1.5),
                 }
-                .apply(s, pd, beam, i, fr);
+                .apply(s, pd, beam, i, n);
             });
             //
         }
         Mode::RaisingBeams { pd } => {
             // let angle = (s.pd(pd) + fr * 2.0) % 1.0;
             let col = s.palette.color0(s, 0.0);
+            let n = l.beams.len();
             l.for_each_beam(|beam, i, fr| {
-                BeamPattern::RaisingBeams.apply(s, pd, beam, i, fr);
+                BeamPattern::RaisingBeams.apply(s, pd, beam, i, n);
                 let angle = (s.pd(pd) + fr * 2.0) % 1.0;
                 // // let
                 // let pitch = if angle < 0.5 {
@@ -719,8 +1010,9 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
         Mode::Break { beams } => {
             if let Some(beams) = beams {
                 let col = s.palette.color0(s, 0.0);
+                let n = l.beams.len();
                 l.for_each_beam(|beam, i, fr| {
-                    beams.apply(s, Pd(4, 1), beam, i, fr);
+                    beams.apply(s, Pd(4, 1), beam, i, n);
                     beam.color = col;
                 });
             }
@@ -728,18 +1020,31 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
         _ => {}
     }
 
+    // Spider movement picked on the pad
+    if let Some(spider) = s.spider {
+        l.for_each_spider(|sp, i, fr| spider.apply(s, sp, i, fr));
+    }
+
+    render_holds(s, l);
+
+    // Live tweaks from the Launch Control knobs
+    l.for_each_beam(|beam, i, fr| {
+        beam.pitch = (beam.pitch + s.beam_pitch).clamp(0.0, 1.0);
+        beam.yaw = (beam.yaw + s.beam_yaw).clamp(0.0, 1.0);
+    });
+
     // Global brightness
     l.map_colors(|c| c * s.brightness);
 
-    if let Some(ManualBeat { t0, t1, pd0, pd1, r }) = s.beat {
+    if let Some(ManualBeat { t0, t1, pd0, pd1, r0, r1 }) = s.beat {
         let fr0 = {
             let dt = s.t - t0;
             let len = (60.0 / s.bpm) * pd0.fr();
 
             if dt >= len {
-                r.hi
+                r0.hi
             } else {
-                (dt / len).ramp(1.0).lerp(r).in_quad()
+                (dt / len).ramp(1.0).lerp(r0).in_quad()
             }
         };
 
@@ -748,9 +1053,9 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
             let len = (60.0 / s.bpm) * pd1.fr();
 
             if dt >= len {
-                r.hi
+                r1.hi
             } else {
-                (dt / len).ramp(1.0).lerp(r).in_quad()
+                (dt / len).ramp(1.0).lerp(r1).in_quad()
             }
         };
 
@@ -761,15 +1066,18 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
             spider.color1 = spider.color1 * fr1;
         });
         l.for_each_bar(|bar, i, fr| bar.color = bar.color * fr1);
-        l.strobe.color = l.strobe.color * fr0;
+        l.for_each_strobe(|strobe, i, fr| strobe.color = strobe.color * fr0);
     }
 
-    l.laser.size = 0.75;
-    l.laser.pattern = LaserPattern::LineX;
-    l.laser.y = 0.375;
-    l.laser.x = s.pd(Pd(4, 1)).tri(1.0) + 0.25 * 0.25;
-    l.laser.color = LaserColor::from_rgb(s.palette.color0(s, 0.0).into());
-    //l.laser.color = LaserColor::RGB;
+    l.for_each_laser(|laser, i, fr| {
+        laser.size = s.laser_size;
+        laser.rotate = s.laser_rotate;
+        laser.pattern = LaserPattern::LineX;
+        laser.y = 0.375;
+        laser.x = s.pd(Pd(4, 1)).tri(1.0) + 0.25 * 0.25;
+        laser.color = LaserColor::from_rgb(s.palette.color0(s, 0.0).into());
+        //laser.color = LaserColor::RGB;
+    });
 
     // for b in &mut l.beams {
     //     b.pitch = s.test4;
@@ -792,12 +1100,390 @@ pub fn render_lights(s: &mut State, l: &mut Lights) {
     // }
     // l.beams[2].yaw = s.test2;
     // l.beams[3].yaw = s.test3;
+}
+
+///////////////////////// PAD LAYOUT /////////////////////////
+
+/// What pressing a pad does.
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    /// Tap along to the beat
+    Tap,
+    /// Apply the tapped tempo, or reset the phase if nothing was tapped
+    TapApply,
+    /// Manual beat of the given length on the left or right side
+    Beat0(Pd),
+    Beat1(Pd),
+    Mode(Mode),
+    Palette(Palette),
+    /// Move the spiders with this pattern whatever the mode, or go back to the mode's own
+    Spider(Option<SpiderPattern>),
+    Brightness(f64),
+    /// Layer over the mode while held
+    Hold(Layer),
+}
+
+/// The color a pad shows in debug mode.
+#[derive(Clone, Copy, Debug)]
+pub enum Swatch {
+    Fixed(Rgb),
+    /// The palette's first or second color
+    Color0,
+    Color1,
+    /// A palette's first color as it looks now
+    Palette(Palette),
+    /// A wave running up the manual beat buttons at the beat
+    Wave,
+}
+
+/// A cell on the pad grid.
+#[derive(Clone, Copy, Debug)]
+pub struct Cell {
+    pub x: i8,
+    pub y: i8,
+    pub action: Action,
+    pub swatch: Swatch,
+    /// Flash the swatch to the beat, at this period
+    pub pulse: Option<Pd>,
+}
+
+/// A page of cells, selected by one of the side buttons.
+#[derive(Clone, Debug)]
+pub struct Page {
+    pub name: &'static str,
+    pub cells: Vec<Cell>,
+}
+
+/// The pad grid. Both input handling and the debug mode colors come from here.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    /// Cells on every page
+    global: Vec<Cell>,
+    pages: Vec<Page>,
+}
+
+impl Layout {
+    /// Check there's a side button for each page, the cells are on the grid, and none of them overlap.
+    pub fn new(global: Vec<Cell>, pages: Vec<Page>) -> Result<Self> {
+        anyhow::ensure!(pages.len() <= 8, "{} pages laid out, but there are only 8 side buttons", pages.len());
+
+        let layout = Self { global, pages };
+        for page in 0..layout.pages.len().max(1) {
+            let name = layout.name(page);
+            let cells = layout.cells(page).collect_vec();
+            for (i, &&Cell { x, y, action, .. }) in cells.iter().enumerate() {
+                anyhow::ensure!((0..8).contains(&x) && (0..8).contains(&y), "Pad ({x}, {y}) on page {name} is off the grid");
+                if let Some(other) = cells[..i].iter().find(|o| (o.x, o.y) == (x, y)) {
+                    anyhow::bail!("Pad ({x}, {y}) on page {name} is laid out twice, as {:?} and {action:?}", other.action);
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn name(&self, page: usize) -> &'static str {
+        self.pages.get(page).map_or("", |p| p.name)
+    }
 
-    l.send();
+    pub fn get(&self, page: usize, x: i8, y: i8) -> Option<&Cell> {
+        self.cells(page).find(|c| (c.x, c.y) == (x, y))
+    }
+
+    /// Cells on `page`, including the global ones.
+    pub fn cells(&self, page: usize) -> impl Iterator<Item = &Cell> {
+        self.global.iter().chain(self.pages.get(page).into_iter().flat_map(|p| &p.cells))
+    }
+}
+
+/// Brightness presets, dimmest first.
+const BRIGHTNESS: [f64; 8] = [0.07, 0.1, 0.125, 0.3, 0.4, 0.6, 0.8, 1.0];
+
+/// The pad layout we play with.
+pub fn pad_layout() -> Result<Layout> {
+    use Action::{Beat0, Beat1, Brightness, Hold, Spider, Tap, TapApply};
+    use Swatch::{Color0, Color1, Fixed, Wave};
+
+    let cell = |x, y, action, swatch| Cell { x, y, action, swatch, pulse: None };
+    let pulse = |x, y, action, swatch, pd| Cell { x, y, action, swatch, pulse: Some(pd) };
+    let auto = |pd, beam| Action::Mode(Mode::AutoBeat { pd, r: (0.2..1.0).into(), beam });
+    let chase = |pd| Action::Mode(Mode::Chase { pd, beam: BeamPattern::Twisting });
+    let on = |beams| Action::Mode(Mode::On { beams: Some(beams) });
+    let brk = |beams| Action::Mode(Mode::Break { beams: Some(beams) });
+    let strobe = |pd, duty| Action::Mode(Mode::Strobe { pd, duty });
+    let palette = |x, y, palette| cell(x, y, Action::Palette(palette), Swatch::Palette(palette));
+
+    let global = vec![
+        // Beatmatch, and apply
+        cell(0, 7, Tap, Fixed(Rgb::BLACK)),
+        cell(7, 7, TapApply, Fixed(Rgb::BLACK)),
+        // Left and right edges: manual beats
+        cell(0, 0, Beat0(Pd(4, 1)), Wave),
+        cell(0, 1, Beat0(Pd(2, 1)), Wave),
+        cell(0, 2, Beat0(Pd(1, 1)), Wave),
+        cell(0, 3, Beat0(Pd(1, 2)), Wave),
+        cell(0, 4, Beat0(Pd(1, 4)), Wave),
+        cell(7, 0, Beat1(Pd(4, 1)), Wave),
+        cell(7, 1, Beat1(Pd(2, 1)), Wave),
+        cell(7, 2, Beat1(Pd(1, 1)), Wave),
+        cell(7, 3, Beat1(Pd(1, 2)), Wave),
+        cell(7, 4, Beat1(Pd(1, 4)), Wave),
+    ];
+
+    let main = vec![
+        // y=0: Lights off, or a brief pause/break
+        cell(1, 0, Action::Mode(Mode::Off), Fixed(Rgb::BLACK)),
+        cell(2, 0, brk(BeamPattern::Out), Fixed(Rgb::BLACK)),
+        cell(3, 0, Action::Mode(Mode::RaisingBeams { pd: Pd(4, 1) }), Fixed(Rgb::BLACK)),
+        cell(4, 0, brk(BeamPattern::WaveY), Fixed(Rgb::BLACK)),
+        cell(5, 0, Action::Mode(Mode::Whirl { pd: Pd(16, 1) }), Fixed(Rgb::BLACK)),
+        cell(6, 0, brk(BeamPattern::UpDownWave), Fixed(Rgb::BLACK)),
+        // y=1: Solid patterns
+        cell(1, 1, Action::Mode(Mode::On { beams: None }), Color0),
+        cell(2, 1, on(BeamPattern::Twisting), Color0),
+        cell(3, 1, on(BeamPattern::Square), Color0),
+        cell(4, 1, on(BeamPattern::Whirl), Color1),
+        cell(5, 1, on(BeamPattern::SnapX), Color1),
+        cell(6, 1, on(BeamPattern::WaveY), Color1),
+        // y=2: Pd(4, 1) patterns
+        pulse(1, 2, auto(Pd(4, 1), BeamPattern::Square), Color0, Pd(1, 1)),
+        pulse(2, 2, auto(Pd(4, 1), BeamPattern::RaisingBeams), Color0, Pd(1, 1)),
+        pulse(3, 2, auto(Pd(4, 1), BeamPattern::Twisting), Color0, Pd(1, 1)),
+        pulse(4, 2, auto(Pd(4, 1), BeamPattern::Whirl), Color1, Pd(1, 1)),
+        pulse(5, 2, auto(Pd(4, 1), BeamPattern::UpDownWave), Color1, Pd(1, 1)),
+        pulse(6, 2, auto(Pd(4, 1), BeamPattern::SnapX), Color1, Pd(1, 1)),
+        // y=3: Pd(2, 1) patterns
+        pulse(1, 3, auto(Pd(2, 1), BeamPattern::Square), Color0, Pd(1, 2)),
+        pulse(2, 3, auto(Pd(2, 1), BeamPattern::RaisingBeams), Color0, Pd(1, 2)),
+        pulse(3, 3, auto(Pd(2, 1), BeamPattern::Twisting), Color0, Pd(1, 2)),
+        pulse(4, 3, auto(Pd(2, 1), BeamPattern::Whirl), Color1, Pd(1, 2)),
+        pulse(5, 3, auto(Pd(2, 1), BeamPattern::UpDownWave), Color1, Pd(1, 2)),
+        pulse(6, 3, auto(Pd(2, 1), BeamPattern::WaveY), Color1, Pd(1, 2)),
+        // y=4: Pd(1, 1) patterns
+        pulse(1, 4, auto(Pd(1, 1), BeamPattern::Square), Color0, Pd(1, 4)),
+        pulse(2, 4, auto(Pd(1, 1), BeamPattern::RaisingBeams), Color0, Pd(1, 4)),
+        pulse(3, 4, auto(Pd(1, 1), BeamPattern::Twisting), Color0, Pd(1, 4)),
+        pulse(4, 4, auto(Pd(1, 1), BeamPattern::Whirl), Color1, Pd(1, 4)),
+        pulse(5, 4, auto(Pd(1, 1), BeamPattern::UpDownWave), Color1, Pd(1, 4)),
+        pulse(6, 4, auto(Pd(1, 1), BeamPattern::WaveY), Color1, Pd(1, 4)),
+        // y=5: Strobes
+        pulse(0, 5, Action::Mode(Mode::Strobe0 { pd: Pd(1, 8), duty: 1.0 }), Color0, Pd(1, 16)),
+        pulse(1, 5, Action::Mode(Mode::Strobe1 { pd: Pd(1, 8), duty: 1.0 }), Color0, Pd(1, 16)),
+        pulse(2, 5, strobe(Pd(1, 4), 1.0), Color0, Pd(1, 16)),
+        pulse(3, 5, strobe(Pd(1, 8), 1.0), Color0, Pd(1, 16)),
+        // Break with the beams pointed at the center
+        cell(4, 5, brk(BeamPattern::Center), Fixed(Rgb::BLACK)),
+        pulse(5, 5, Action::Mode(Mode::ChaseNotColorful { pd: Pd(1, 4) }), Color1, Pd(1, 16)),
+        pulse(6, 5, chase(Pd(1, 2)), Fixed(Rgb::WHITE), Pd(1, 32)),
+        pulse(7, 5, chase(Pd(1, 4)), Fixed(Rgb::WHITE), Pd(1, 32)),
+        // y=6, y=7: Colorz
+        cell(1, 7, Action::Palette(Palette::Solid(Rgbw::RED)), Fixed(Rgb::RED)),
+        cell(2, 7, Action::Palette(Palette::Split(Rgbw::RED, Rgbw::BLUE)), Fixed(Rgb::RED)),
+        cell(3, 7, Action::Palette(Palette::Split(Rgbw::MAGENTA, Rgbw::RED)), Fixed(Rgb::MAGENTA)),
+        cell(4, 7, Action::Palette(Palette::Split(Rgbw::VIOLET, Rgbw::BLUE)), Fixed(Rgb::PINK)),
+        palette(5, 7, Palette::Rainbow),
+        palette(6, 7, Palette::RainbowOsc),
+        cell(0, 6, Action::Palette(Palette::Solid(Rgbw::WHITE)), Fixed(Rgb::WHITE)),
+        cell(1, 6, Action::Palette(Palette::Solid(Rgbw::BLUE)), Fixed(Rgb::BLUE)),
+        cell(2, 6, Action::Palette(Palette::Split(Rgbw::BLUE, Rgbw::CYAN)), Fixed(Rgb::BLUE)),
+        cell(3, 6, Action::Palette(Palette::Split(Rgbw::CYAN, Rgbw::LIME)), Fixed(Rgb::CYAN)),
+        cell(4, 6, Action::Palette(Palette::Split(Rgbw::MINT, Rgbw::MINT)), Fixed(Rgb::CYAN)),
+        cell(5, 6, Action::Palette(Palette::Split(Rgbw::LIME, Rgbw::LIME)), Fixed(Rgb::MINT)),
+        palette(6, 6, Palette::RgbOsc),
+        cell(7, 6, Action::Palette(Palette::Solid(Rgbw::RGBW)), Fixed(Rgb::WHITE)),
+    ];
+
+    let beams = vec![
+        // y=0..2: Beams only
+        cell(1, 0, brk(BeamPattern::Down), Color0),
+        cell(2, 0, brk(BeamPattern::Out), Color0),
+        cell(3, 0, brk(BeamPattern::Center), Color0),
+        cell(4, 0, brk(BeamPattern::SpreadOut), Color0),
+        cell(5, 0, brk(BeamPattern::SpreadIn), Color0),
+        cell(6, 0, brk(BeamPattern::CrissCross { pitch: 0.3 }), Color0),
+        cell(1, 1, brk(BeamPattern::Cross { pitch: 0.3, angle: None, fanning: None }), Color0),
+        cell(2, 1, brk(BeamPattern::Cross { pitch: 0.2, angle: None, fanning: Some(1.5) }), Color0),
+        cell(3, 1, brk(BeamPattern::DarthMaul), Color0),
+        cell(4, 1, brk(BeamPattern::SnapY), Color0),
+        cell(5, 1, brk(BeamPattern::RaisingBeams), Color0),
+        cell(6, 1, brk(BeamPattern::Twisting), Color0),
+        cell(1, 2, brk(BeamPattern::WaveY), Color0),
+        cell(2, 2, brk(BeamPattern::SnapX), Color0),
+        cell(3, 2, brk(BeamPattern::Square), Color0),
+        cell(4, 2, brk(BeamPattern::Whirl), Color0),
+        cell(5, 2, brk(BeamPattern::UpDownWave), Color0),
+        // y=3, y=4: Everything on
+        cell(1, 3, on(BeamPattern::Down), Color1),
+        cell(2, 3, on(BeamPattern::Out), Color1),
+        cell(3, 3, on(BeamPattern::Center), Color1),
+        cell(4, 3, on(BeamPattern::SpreadOut), Color1),
+        cell(5, 3, on(BeamPattern::SpreadIn), Color1),
+        cell(6, 3, on(BeamPattern::CrissCross { pitch: 0.3 }), Color1),
+        cell(1, 4, on(BeamPattern::DarthMaul), Color1),
+        cell(2, 4, on(BeamPattern::SnapY), Color1),
+        cell(3, 4, on(BeamPattern::RaisingBeams), Color1),
+        cell(4, 4, on(BeamPattern::UpDownWave), Color1),
+        // y=5: Flashing to the beat
+        pulse(0, 5, auto(Pd(2, 1), BeamPattern::Down), Color0, Pd(1, 2)),
+        pulse(1, 5, auto(Pd(2, 1), BeamPattern::Out), Color0, Pd(1, 2)),
+        pulse(2, 5, auto(Pd(2, 1), BeamPattern::Center), Color0, Pd(1, 2)),
+        pulse(3, 5, auto(Pd(2, 1), BeamPattern::SpreadOut), Color0, Pd(1, 2)),
+        pulse(4, 5, auto(Pd(2, 1), BeamPattern::SpreadIn), Color1, Pd(1, 2)),
+        pulse(5, 5, auto(Pd(2, 1), BeamPattern::CrissCross { pitch: 0.3 }), Color1, Pd(1, 2)),
+        pulse(6, 5, auto(Pd(2, 1), BeamPattern::DarthMaul), Color1, Pd(1, 2)),
+        pulse(7, 5, auto(Pd(2, 1), BeamPattern::SnapY), Color1, Pd(1, 2)),
+    ];
+
+    let mut spiders = vec![
+        // y=0: Back to the mode's own movement, or still
+        cell(1, 0, Spider(None), Fixed(Rgb::WHITE)),
+        cell(2, 0, Spider(Some(SpiderPattern::Up)), Color0),
+        cell(3, 0, Spider(Some(SpiderPattern::Down)), Color0),
+    ];
+    // y=1..4: Moving, slowest on the bottom
+    for (y, pd) in (1..).zip([Pd(4, 1), Pd(2, 1), Pd(1, 1), Pd(1, 2)]) {
+        spiders.extend([
+            pulse(1, y, Spider(Some(SpiderPattern::Wave { pd })), Color0, pd),
+            pulse(2, y, Spider(Some(SpiderPattern::Alternate { pd })), Color0, pd),
+            pulse(3, y, Spider(Some(SpiderPattern::Snap { pd })), Color0, pd),
+        ]);
+    }
+
+    let palettes = vec![
+        // y=0, y=1: Solid colors
+        palette(1, 0, Palette::Solid(Rgbw::RED)),
+        palette(2, 0, Palette::Solid(Rgbw::ORANGE)),
+        palette(3, 0, Palette::Solid(Rgbw::YELLOW)),
+        palette(4, 0, Palette::Solid(Rgbw::LIME)),
+        palette(5, 0, Palette::Solid(Rgbw::MINT)),
+        palette(6, 0, Palette::Solid(Rgbw::CYAN)),
+        palette(1, 1, Palette::Solid(Rgbw::BLUE)),
+        palette(2, 1, Palette::Solid(Rgbw::VIOLET)),
+        palette(3, 1, Palette::Solid(Rgbw::MAGENTA)),
+        palette(4, 1, Palette::Solid(Rgbw::PINK)),
+        palette(5, 1, Palette::Solid(Rgbw::WHITE)),
+        palette(6, 1, Palette::Solid(Rgbw::RGBW)),
+        // y=2, y=3: Two colors
+        palette(1, 2, Palette::Split(Rgbw::RED, Rgbw::BLUE)),
+        palette(2, 2, Palette::Split(Rgbw::RED, Rgbw::ORANGE)),
+        palette(3, 2, Palette::Split(Rgbw::ORANGE, Rgbw::CYAN)),
+        palette(4, 2, Palette::Split(Rgbw::YELLOW, Rgbw::MAGENTA)),
+        palette(5, 2, Palette::Split(Rgbw::LIME, Rgbw::VIOLET)),
+        palette(6, 2, Palette::Split(Rgbw::MINT, Rgbw::PINK)),
+        palette(1, 3, Palette::Split(Rgbw::BLUE, Rgbw::CYAN)),
+        palette(2, 3, Palette::Split(Rgbw::CYAN, Rgbw::LIME)),
+        palette(3, 3, Palette::Split(Rgbw::VIOLET, Rgbw::BLUE)),
+        palette(4, 3, Palette::Split(Rgbw::MAGENTA, Rgbw::RED)),
+        palette(5, 3, Palette::Split(Rgbw::PINK, Rgbw::WHITE)),
+        palette(6, 3, Palette::Split(Rgbw::WHITE, Rgbw::BLUE)),
+        // y=5: Moving colors
+        palette(1, 5, Palette::Rainbow),
+        palette(2, 5, Palette::RainbowOsc),
+        palette(3, 5, Palette::RgbOsc),
+    ];
+
+    let mut fx = vec![];
+    // y=0..2: Strobes on everything, the left side, or the right side, slowest on the left
+    for (x, pd) in (1..).zip([Pd(1, 1), Pd(1, 2), Pd(1, 4), Pd(1, 8), Pd(1, 16)]) {
+        fx.extend([
+            pulse(x, 0, Action::Mode(Mode::Strobe { pd, duty: 1.0 }), Color0, pd.div(2)),
+            pulse(x, 1, Action::Mode(Mode::Strobe0 { pd, duty: 1.0 }), Color0, pd.div(2)),
+            pulse(x, 2, Action::Mode(Mode::Strobe1 { pd, duty: 1.0 }), Color1, pd.div(2)),
+        ]);
+    }
+    // y=3, y=4: Chases
+    for (x, pd) in (1..).zip([Pd(1, 1), Pd(1, 2), Pd(1, 4), Pd(1, 8)]) {
+        fx.extend([
+            pulse(x, 3, chase(pd), Fixed(Rgb::WHITE), pd.div(4)),
+            pulse(x, 4, Action::Mode(Mode::ChaseNotColorful { pd }), Color1, pd.div(4)),
+        ]);
+    }
+    // y=6: Brightness, dimmest on the left, like the Launch Control's track buttons
+    for (x, brightness) in (0..).zip(BRIGHTNESS) {
+        fx.push(cell(x, 6, Brightness(brightness), Fixed(Rgb::WHITE * brightness)));
+    }
+    // y=5: Holds, only while pressed
+    fx.extend([
+        cell(0, 5, Hold(Layer::Color(Rgbw::WHITE)), Fixed(Rgb::WHITE)),
+        cell(1, 5, Hold(Layer::Blackout), Fixed(Rgb::RED)),
+        pulse(2, 5, Hold(Layer::Strobe(Pd(1, 4))), Fixed(Rgb::WHITE), Pd(1, 8)),
+        pulse(3, 5, Hold(Layer::Strobe(Pd(1, 8))), Fixed(Rgb::WHITE), Pd(1, 16)),
+        pulse(4, 5, Hold(Layer::Strobe(Pd(1, 16))), Fixed(Rgb::WHITE), Pd(1, 32)),
+        pulse(5, 5, Hold(Layer::Beams { pd: Pd(4, 1), beam: BeamPattern::RaisingBeams }), Color0, Pd(1, 1)),
+        pulse(6, 5, Hold(Layer::Beams { pd: Pd(2, 1), beam: BeamPattern::WaveY }), Color0, Pd(1, 2)),
+        pulse(7, 5, Hold(Layer::Beams { pd: Pd(1, 1), beam: BeamPattern::Square }), Color0, Pd(1, 4)),
+    ]);
+    // y=7: Color holds
+    for (x, col) in (1..).zip([Rgbw::RED, Rgbw::ORANGE, Rgbw::LIME, Rgbw::CYAN, Rgbw::BLUE, Rgbw::MAGENTA]) {
+        fx.push(cell(x, 7, Hold(Layer::Color(col)), Fixed(col.into())));
+    }
+
+    Layout::new(
+        global,
+        vec![
+            Page { name: "Main", cells: main },
+            Page { name: "Beams", cells: beams },
+            Page { name: "Spiders", cells: spiders },
+            Page { name: "Palettes", cells: palettes },
+            Page { name: "FX", cells: fx },
+        ],
+    )
 }
 
 ///////////////////////// PAD /////////////////////////
 
+/// How hard a held pad needs to be pressed to send aftertouch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Aftertouch {
+    /// Don't send aftertouch at all
+    Off,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+/// How aftertouch maps onto a held strobe's duty or a held brightness pad, once past the threshold set by `Aftertouch`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum AftertouchCurve {
+    /// As hard as the pad is pressed
+    #[default]
+    Linear,
+    /// Most of the range in a light press
+    Soft,
+    /// Most of the range saved for a firm press
+    Hard,
+}
+
+impl AftertouchCurve {
+    pub fn apply(self, fr: f64) -> f64 {
+        match self {
+            AftertouchCurve::Linear => fr,
+            AftertouchCurve::Soft => fr.out_quad(),
+            AftertouchCurve::Hard => fr.in_quad(),
+        }
+    }
+}
+
+/// Set up a freshly connected pad.
+pub fn setup_pad(pad: &mut Midi<LaunchpadX>, aftertouch: Aftertouch) {
+    use launchpad_x::{types::*, *};
+    pad.send(match aftertouch {
+        Aftertouch::Off => Output::Pressure(Pressure::Off, PressureCurve::Medium),
+        Aftertouch::Low => Output::Pressure(Pressure::Polyphonic, PressureCurve::Low),
+        Aftertouch::Medium => Output::Pressure(Pressure::Polyphonic, PressureCurve::Medium),
+        Aftertouch::High => Output::Pressure(Pressure::Polyphonic, PressureCurve::High),
+    });
+    pad.send(Output::Brightness(1.0));
+    // Start from a blank slate, the next `render_pad()` repaints everything
+    pad.send(Output::Clear);
+}
+
 pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
     use self::Mode;
     use launchpad_x::{types::*, *};
@@ -812,127 +1498,38 @@ pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
         let color0: Rgb = s.palette.color0(s, 0.0).into();
         let color1: Rgb = s.palette.color1(s, 0.0).into();
 
-        // mod colors
-        // rgb(2, 6, Rgb::BLACK);
-        // rgb(3, 6, Rgb::WHITE);
-        // rgb(4, 6, Rgb::hsv(s.phi(16, 1), 1.0, 1.0));
-        // rgb(5, 6, Rgb::WHITE);
-
-        // y=0: Lights off, or a brief pause/break
-        set(1, 0, Rgb::BLACK);
-        set(2, 0, Rgb::BLACK);
-        set(3, 0, Rgb::BLACK);
-        set(4, 0, Rgb::BLACK);
-        set(5, 0, Rgb::BLACK);
-        set(6, 0, Rgb::BLACK);
-
-        // y=1: Solid patterns
-        set(1, 1, color0);
-        set(2, 1, color0);
-        set(3, 1, color0);
-        set(4, 1, color1);
-        set(5, 1, color1);
-        set(6, 1, color1);
-
-        let beat = |pd: Pd| s.pd(pd.mul(4)).ramp(1.0).inv().in_quad();
-        let beat11 = beat(Pd(1, 1));
-        let beat12 = beat(Pd(1, 2));
-        let beat14 = beat(Pd(1, 4));
-        let beat116 = beat(Pd(1, 16));
-        let beat132 = beat(Pd(1, 32));
-
-        // y=2: Pd(1, 1) patterns
-        set(1, 2, color0 * beat11);
-        set(2, 2, color0 * beat11);
-        set(3, 2, color0 * beat11);
-        set(4, 2, color1 * beat11);
-        set(5, 2, color1 * beat11);
-        set(6, 2, color1 * beat11);
-
-        // y=3: Pd(1, 2) patterns
-        set(1, 3, color0 * beat12);
-        set(2, 3, color0 * beat12);
-        set(3, 3, color0 * beat12);
-        set(4, 3, color1 * beat12);
-        set(5, 3, color1 * beat12);
-        set(6, 3, color1 * beat12);
-
-        // y=4: Pd(1, 4) patterns
-        set(1, 4, color0 * beat14);
-        set(2, 4, color0 * beat14);
-        set(3, 4, color0 * beat14);
-        set(4, 4, color1 * beat14);
-        set(5, 4, color1 * beat14);
-        set(6, 4, color1 * beat14);
-
-        // y=5: Strobes
-        set(0, 5, color0 * beat116);
-        set(1, 5, color0 * beat116);
-        set(2, 5, color0 * beat116);
-        set(3, 5, color0 * beat116);
-        set(4, 5, color1 * beat116);
-        set(5, 5, color1 * beat116);
-        set(6, 5, Rgb::WHITE * beat132);
-        set(7, 5, Rgb::WHITE * beat132);
-        // set(
-        //     6,
-        //     7,
-        //     match s.phi(1, 4) {
-        //         ..0.33 => Rgb::RED,
-        //         0.33..0.66 => Rgb::LIME,
-        //         _ => Rgb::BLUE,
-        //     },
-        // );
-
-        // y=6, y=7: Colorz
-        //   red, red/blue, red/purple, pink,
-        //   blue, blue/green, blue/teal, green,
-        //   rainbow, white, rgbw
-        set(1, 7, Rgb::RED);
-        set(2, 7, Rgb::RED);
-        set(3, 7, Rgb::MAGENTA);
-        set(4, 7, Rgb::PINK);
-        set(5, 7, Rgb::hsv(s.phi(16, 1), 1.0, 1.0));
-        set(6, 7, Palette::RainbowOsc.color0(s, 0.0).into());
-
-        set(0, 6, Rgb::WHITE);
-        set(1, 6, Rgb::BLUE);
-        set(2, 6, Rgb::BLUE);
-        set(3, 6, Rgb::CYAN);
-        set(4, 6, Rgb::CYAN);
-        set(5, 6, Rgb::MINT);
-        set(6, 6, Palette::RgbOsc.color0(s, 0.0).into());
-        set(7, 6, Rgb::WHITE);
-
-        // set(2, 6, Rgb::CYAN);
-        // set(3, 6, Rgb::BLUE);
-        // set(4, 6, Rgb::VIOLET);
-        // set(5, 6, Rgb::MAGENTA);
-
-        // set(2, 7, Rgb::RED);
-        // set(3, 7, Rgb::ORANGE);
-        // set(4, 7, Rgb::YELLOW);
-        // set(5, 7, Rgb::LIME);
-
-        // Left and right edges: manual beat buttons
-        for i in 0..=4 {
-            // Upwards propagating wave at BPM
-            let col = Rgb::WHITE * (s.phi - i as f64 * 0.2).fsin(2.0);
-            set(0, i, col);
-            set(7, i, col);
+        // Grid: a preview of what each cell does
+        for cell in s.layout.clone().cells(s.page) {
+            let col = match cell.swatch {
+                Swatch::Fixed(col) => col,
+                Swatch::Color0 => color0,
+                Swatch::Color1 => color1,
+                Swatch::Palette(p) => p.color0(s, 0.0).into(),
+                // Upwards propagating wave at BPM
+                Swatch::Wave => Rgb::WHITE * (s.phi - cell.y as f64 * 0.2).fsin(2.0),
+            };
+            let env = cell.pulse.map_or(1.0, |pd| s.pd(pd.mul(4)).ramp(1.0).inv().in_quad());
+            set(cell.x, cell.y, col * env);
         }
 
-        // Top and right outer buttons: alpha selectors (TODO)
-        for i in 0..=7 {
-            set(i, 8, Rgb::WHITE);
-            set(8, i, Rgb::VIOLET);
-        }
+        // Top up/down arrows: step the multiplier
+        set(0, 8, Rgb::LIME);
+        set(1, 8, Rgb::LIME);
+        // Top left/right arrows: nudge, brighter while held
+        set(2, 8, Rgb::ORANGE * if s.nudge_back { 1.0 } else { 0.3 });
+        set(3, 8, Rgb::ORANGE * if s.nudge_fwd { 1.0 } else { 0.3 });
+        // Top session/note: bpm fine adjust
+        set(4, 8, Rgb::CYAN);
+        set(5, 8, Rgb::CYAN);
+        // Top custom/capture: lasers on/off, and back out of debug mode
+        set(6, 8, Rgb::RED);
+        set(7, 8, Rgb::WHITE);
 
         // Top left/right: beatmatch buttons
         set(0, 7, Rgb::VIOLET);
         set(7, 7, Rgb::VIOLET);
     } else {
-        if let Some(ManualBeat { t0, t1, pd0, pd1, r }) = s.beat {
+        if let Some(ManualBeat { t0, t1, pd0, pd1, r0, r1 }) = s.beat {
             for i in 0..8 {
                 for j in 0..8 {
                     let fr0 = {
@@ -946,7 +1543,7 @@ pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
                         } else if t > 1.0 {
                             0.0
                         } else {
-                            t.ramp(1.0).lerp(r).in_quad()
+                            t.ramp(1.0).lerp(r0).in_quad()
                         }
                     };
 
@@ -961,7 +1558,7 @@ pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
                         } else if t > 1.0 {
                             0.0
                         } else {
-                            t.ramp(1.0).lerp(r).in_quad()
+                            t.ramp(1.0).lerp(r1).in_quad()
                         }
                     };
 
@@ -1064,6 +1661,7 @@ pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
                     }
                 }
                 Mode::Strobe { pd, duty } | Mode::Strobe0 { pd, duty } | Mode::Strobe1 { pd, duty } => {
+                    let duty = s.duty.unwrap_or(duty);
                     let env = s.pd(pd).square(1.0, duty.in_exp().lerp(1.0..0.5));
                     let col = Rgb::from(s.palette.color0(s, 0.0)) * env;
 
@@ -1101,6 +1699,30 @@ pub fn render_pad(s: &mut State, pad: &mut Midi<LaunchpadX>) {
         }
     }
 
+    // Blink queued changes until they launch
+    let blink = if (s.t * 4.0).fract() < 0.5 { Rgb::WHITE } else { Rgb::BLACK };
+    let queued = [
+        s.next_mode.map(|l| (l.page, l.x, l.y)),
+        s.next_palette.map(|l| (l.page, l.x, l.y)),
+    ];
+    for (page, x, y) in queued.into_iter().flatten() {
+        if page == s.page {
+            set(x, y, blink);
+        }
+    }
+
+    // Right side buttons: pages, with the one shown lit up
+    for page in 0..s.layout.pages() {
+        set(8, 7 - page as i8, if page == s.page { Rgb::WHITE } else { Rgb::VIOLET * 0.2 });
+    }
+
+    // While tapping, flash the beatmatch buttons at the tempo tapped so far
+    if let (true, Some(bpm), Some(last)) = (s.tap.tapping(s.t), s.tap.bpm(), s.tap.last()) {
+        let col = Rgb::LIME * ((s.t - last) * bpm / 60.0).fmod(1.0).square(1.0, 0.25);
+        set(0, 7, col);
+        set(7, 7, col);
+    }
+
     // Beat indicator
     set(
         8,
@@ -1144,17 +1766,297 @@ fn spiral(time: f64, x: i8, y: i8, speed: f64) -> f64 {
 
 ///////////////////////// CTRL /////////////////////////
 
-pub fn render_ctrl(s: &mut State, ctrl: &mut Midi<LaunchControlXL>) {
-    use self::Mode;
-    use launch_control_xl::{types::*, *};
+/// A Launch Control LED, and the color it shows.
+pub type CtrlLed = (launch_control_xl::types::Pos, launch_control_xl::types::Color);
+
+pub fn setup_ctrl(ctrl: &mut Midi<LaunchControlXL>) {
+    use launch_control_xl::*;
+    // Start from a blank slate, matching the empty `ctrl_leds`
+    ctrl.send(Output::Clear);
+}
+
+pub fn render_ctrl(s: &mut State, l: &Lights, ctrl: &mut Midi<LaunchControlXL>) {
+    use launch_control_xl::*;
+
+    let leds = ctrl_leds(s, l);
+    let batch = ctrl_changes(&s.ctrl_leds, &leds);
+    if !batch.is_empty() {
+        ctrl.send(Output::Batch(batch));
+    }
+    s.ctrl_leds = leds;
+}
+
+/// What every Launch Control LED should show.
+fn ctrl_leds(s: &State, l: &Lights) -> Vec<CtrlLed> {
+    use launch_control_xl::types::*;
+
+    let mut leds = vec![];
+
+    // LEDs only have 4 levels, from 0..3
+    let level = |fr: f64| (fr.clamp(0.0, 1.0) * 3.0).round() as u8;
+    let color = |c: fn(u8) -> Color, fr: f64| match level(fr) {
+        0 => Color::Off,
+        n => c(n),
+    };
+
+    // Brightness fader position, as a meter up the knobs above it
+    let fr = s.brightness * 3.0;
+    for (i, pos) in [Pos::Pan(0), Pos::Send(1, 0), Pos::Send(0, 0)].into_iter().enumerate() {
+        leds.push((pos, color(Color::Amber, fr - i as f64)));
+    }
+
+    // Knobs in use: green once they've picked up their parameter, red until then
+    for (row, col) in (0..3).cartesian_product(1..8) {
+        let pos = match row {
+            0 => Pos::Send(0, col as u8),
+            1 => Pos::Send(1, col as u8),
+            _ => Pos::Pan(col as u8),
+        };
+        leds.push((
+            pos,
+            match s.knobs.caught(s, row, col) {
+                Some(true) => Color::Green(2),
+                Some(false) => Color::Red(1),
+                None => Color::Off,
+            },
+        ));
+    }
+
+    // Laser on/off
+    let laser = l.lasers.iter().any(|laser| laser.on);
+    leds.push((Pos::Focus(0), if laser { Color::Green(3) } else { Color::Off }));
+
+    // Active palette group
+    let group = match s.palette {
+        Palette::Rainbow => 1,
+        Palette::RgbOsc | Palette::RainbowOsc => 2,
+        Palette::Solid(_) => 3,
+        Palette::Split(_, _) => 4,
+    };
+    for i in 1..5 {
+        leds.push((Pos::Focus(i), if i == group { Color::Amber(3) } else { Color::Off }));
+    }
+
+    // Beat flashes, stepping along the track buttons over 2 bars with the downbeats in red
+    let beat = s.phi_beat.floor() as u8 % 8;
+    let flash = (1.0 - s.phi_beat.fract()).powi(2);
+    for i in 0..8 {
+        let c = if i % 4 == 0 { Color::Red } else { Color::Green };
+        leds.push((Pos::Control(i), if i == beat { color(c, flash) } else { Color::Off }));
+    }
+
+    leds
+}
+
+/// LEDs in `leds` which changed since `last` was sent. LEDs not sent yet are off.
+fn ctrl_changes(last: &[CtrlLed], leds: &[CtrlLed]) -> Vec<CtrlLed> {
+    use launch_control_xl::types::*;
+
+    let prev = |pos| last.iter().find(|(p, _)| *p == pos).map_or(Color::Off, |&(_, c)| c);
+    leds.iter().filter(|&&(pos, c)| prev(pos) != c).copied().collect()
 }
 
 ///////////////////////// TICK /////////////////////////
 
+/// How much a held nudge button speeds up or slows down `phi`.
+const NUDGE: f64 = 0.04;
+
 pub fn tick(dt: f64, s: &mut State, l: &mut Lights) {
     s.dt = dt;
     s.t += dt;
-    s.phi = (s.phi + (dt * (s.bpm / 60.0) * s.phi_mul)).fmod(16.0);
+
+    let nudge = 1.0 + NUDGE * (s.nudge_fwd as i8 - s.nudge_back as i8) as f64;
+    let beats = dt * (s.bpm / 60.0) * nudge;
+    s.shift(beats);
+
+    if let Some(glide) = &mut s.glide {
+        glide.elapsed += beats;
+        let fr = (glide.elapsed / glide.beats).min(1.0);
+        s.bpm = glide.from + (glide.to - glide.from) * fr.inout_quad();
+        if fr >= 1.0 {
+            s.glide = None;
+        }
+    }
+
+    // After switching multipliers, `phi` is off the beat grid. Ease it back on over a beat or so, rather than snapping.
+    // Triplets only line up every few beats, so leave them be.
+    if s.mul != Multiplier::Triplet {
+        let err = s.phi - s.phi_beat * s.mul.value();
+        s.phi = (s.phi - (err - err.round()) * (1.0 - (-beats).exp())).rem_euclid(16.0);
+    }
+}
+
+///////////////////////// LAUNCH /////////////////////////
+
+/// Change the mode, right away or on the next boundary depending on `quantize`. Pressing a queued button again launches it now.
+fn set_mode(s: &mut State, mode: Mode) {
+    match s.quantize {
+        Quantize::Off => s.mode = mode,
+        _ if s.next_mode.is_some_and(|l| (l.page, l.x, l.y) == (s.page, s.x, s.y)) => launch(s),
+        _ => s.next_mode = Some(Launch { val: mode, page: s.page, x: s.x, y: s.y }),
+    }
+}
+
+/// Change the palette, right away or on the next boundary depending on `quantize`.
+fn set_palette(s: &mut State, palette: Palette) {
+    match s.quantize {
+        Quantize::Off => s.palette = palette,
+        _ if s.next_palette.is_some_and(|l| (l.page, l.x, l.y) == (s.page, s.x, s.y)) => launch(s),
+        _ => s.next_palette = Some(Launch { val: palette, page: s.page, x: s.x, y: s.y }),
+    }
+}
+
+/// Apply any queued changes.
+fn launch(s: &mut State) {
+    if let Some(Launch { val, .. }) = s.next_mode.take() {
+        s.mode = val;
+    }
+    if let Some(Launch { val, .. }) = s.next_palette.take() {
+        s.palette = val;
+    }
+}
+
+///////////////////////// TEMPO ADJUST /////////////////////////
+
+/// Hold to temporarily slow down (`fwd = false`) or speed up (`fwd = true`) the beat, to line it up with the music.
+fn nudge(s: &mut State, fwd: bool, held: bool) {
+    match fwd {
+        true => s.nudge_fwd = held,
+        false => s.nudge_back = held,
+    }
+    s.tapped = Some(s.t);
+}
+
+/// Change the tempo by hand, gliding over `glide_beats`.
+fn set_bpm(s: &mut State, bpm: f64) {
+    let bpm = bpm.max(1.0);
+    match s.glide_beats > 0.0 {
+        true => s.glide = Some(Glide { from: s.bpm, to: bpm, beats: s.glide_beats, elapsed: 0.0 }),
+        false => s.bpm = bpm,
+    }
+    s.tapped = Some(s.t);
+}
+
+/// Fine adjust the tempo by `delta` BPM.
+fn adjust_bpm(s: &mut State, delta: f64) {
+    let bpm = s.glide.map_or(s.bpm, |g| g.to) + delta;
+    set_bpm(s, bpm);
+    log::info!("Adjusted bpm={bpm:.2}");
+}
+
+/// Latch a different bpm multiplier. `phi` carries on from where it is, and drifts back onto the beat.
+fn set_mul(s: &mut State, mul: Multiplier) {
+    s.mul = mul;
+    log::info!("Multiplier {mul:?}");
+}
+
+/// Snap the beat to the nearest bar start, right now.
+fn downbeat(s: &mut State) {
+    let err = (s.phi_beat / 4.0).round() * 4.0 - s.phi_beat;
+    s.shift(err);
+    s.tapped = Some(s.t);
+}
+
+///////////////////////// AUDIO INPUT /////////////////////////
+
+/// Ease `bpm` and `phi` towards the beat detected in the audio input, unless recently tapped.
+pub fn on_audio(s: &mut State, est: Estimate, settings: &audio::Settings) {
+    if s.tapped.is_some_and(|t| s.t - t < settings.tap_override) {
+        return;
+    }
+
+    // Smooth over a couple seconds so a bad estimate doesn't jerk everything around
+    let ease = |secs: f64| 1.0 - (-s.dt / secs).exp();
+    s.glide = None;
+    s.bpm += (est.bpm - s.bpm) * ease(2.0);
+
+    // Align to the bar when the downbeat is clear, otherwise just the beat
+    let (target, len) = match est.bar {
+        Some(bar) => (bar, 4.0),
+        None => (est.phase, 1.0),
+    };
+    let err = (target - s.phi_beat.fmod(len) + len / 2.0).rem_euclid(len) - len / 2.0;
+    s.shift(err * ease(0.5));
+}
+
+///////////////////////// CLOCK INPUT /////////////////////////
+
+/// Lock `bpm` and `phi` to an incoming MIDI clock, and its song position if it's playing.
+pub fn on_clock(s: &mut State, bpm: f64, beat: Option<f64>) {
+    // The clock is already smoothed, so only ease enough to hide jitter
+    let ease = |secs: f64| 1.0 - (-s.dt / secs).exp();
+    s.glide = None;
+    s.bpm += (bpm - s.bpm) * ease(0.25);
+
+    if let Some(beat) = beat {
+        let err = (beat - s.phi_beat + 8.0).rem_euclid(16.0) - 8.0;
+        s.shift(match err.abs() > 0.5 {
+            // Jump straight there after a Start or Song Position
+            true => err,
+            // Otherwise correct for drift gradually
+            false => err * ease(0.25),
+        });
+    }
+}
+
+///////////////////////// TIMECODE /////////////////////////
+
+/// Lock `t` to the timecode at `pos` seconds, and run the show's cues that are due.
+pub fn on_timecode(s: &mut State, l: &mut Lights, pos: f64, cues: &[Cue]) {
+    // Anything timed by `t` moves along with it, so a jump in the timecode doesn't leave taps and beats behind
+    let dt = pos - s.t;
+    s.t = pos;
+    if let Some(tapped) = &mut s.tapped {
+        *tapped += dt;
+    }
+    if let Some(beat) = &mut s.beat {
+        beat.t0 += dt;
+        beat.t1 += dt;
+    }
+    s.tap.shift(dt);
+
+    // Cues are already timed to the music, so they happen right away
+    let quantize = std::mem::take(&mut s.quantize);
+    for cue in cues {
+        log::debug!("Running cue at {:.2}s: {cue:?}", cue.at);
+        if let Some((x, y)) = cue.pad {
+            let page = std::mem::replace(&mut s.page, cue.page);
+            press(s, l, x, y, 1.0);
+            s.page = page;
+        }
+        if let Some(mode) = cue.mode {
+            set_mode(s, mode);
+        }
+        if let Some(palette) = cue.palette {
+            set_palette(s, palette);
+        }
+        if let Some(brightness) = cue.brightness {
+            s.brightness = brightness;
+        }
+    }
+    s.quantize = quantize;
+}
+
+///////////////////////// MIDI LEARN /////////////////////////
+
+/// A control bound with MIDI learn was pressed, released, or moved to `val`, from `0..1`.
+pub fn on_learned(s: &mut State, l: &mut Lights, target: Target, val: f64) {
+    match target {
+        // Pads work from any page, like cues
+        Target::Pad { page, x, y } => {
+            let current = std::mem::replace(&mut s.page, page);
+            match val > 0.0 {
+                true => press(s, l, x, y, val),
+                false => {
+                    aftertouch(s, x, y, None);
+                    s.holds.release(page, x, y);
+                }
+            }
+            s.page = current;
+        }
+        Target::Brightness => s.brightness = val,
+        Target::Param(param) => param.set(s, val),
+    }
 }
 
 ///////////////////////// PAD INPUT /////////////////////////
@@ -1171,264 +2073,317 @@ pub fn on_pad(s: &mut State, l: &mut Lights, pad: &mut Midi<LaunchpadX>, event:
             pad.send(Output::Clear);
         }
         // Toggle laser
-        Input::Custom(true) => l.laser.on = !l.laser.on,
-        // Brightness
-        Input::Record(true) => s.brightness = 0.07,
-        Input::Solo(true) => s.brightness = 0.1,
-        Input::Mute(true) => s.brightness = 0.125,
-        Input::Stop(true) => s.brightness = 0.3,
-        Input::B(true) => s.brightness = 0.4,
-        Input::A(true) => s.brightness = 0.6,
-        Input::Pan(true) => s.brightness = 0.8,
-        Input::Volume(true) => s.brightness = 1.0,
-        // half/double-time
-        Input::Up(true) => s.phi_mul = 2.0,
-        Input::Down(true) => s.phi_mul = 0.5,
+        Input::Custom(true) => l.for_each_laser(|laser, i, fr| laser.on = !laser.on),
+        // Pages, top to bottom
+        Input::Volume(true) => set_page(s, pad, 0),
+        Input::Pan(true) => set_page(s, pad, 1),
+        Input::A(true) => set_page(s, pad, 2),
+        Input::B(true) => set_page(s, pad, 3),
+        Input::Stop(true) => set_page(s, pad, 4),
+        Input::Mute(true) => set_page(s, pad, 5),
+        Input::Solo(true) => set_page(s, pad, 6),
+        Input::Record(true) => set_page(s, pad, 7),
+        // Step the multiplier, e.g. half/double-time
+        Input::Up(true) => set_mul(s, s.mul.faster()),
+        Input::Down(true) => set_mul(s, s.mul.slower()),
+        // Nudge back/forward while held, or both together for a downbeat
+        Input::Left(held) => {
+            nudge(s, false, held);
+            if held && s.nudge_fwd {
+                downbeat(s);
+            }
+        }
+        Input::Right(held) => {
+            nudge(s, true, held);
+            if held && s.nudge_back {
+                downbeat(s);
+            }
+        }
+        // Fine adjust bpm
+        Input::Session(true) => adjust_bpm(s, -0.1),
+        Input::Note(true) => adjust_bpm(s, 0.1),
+        _ => {}
+    }
+
+    // First match on x/y presses only.
+    if let Input::Press(i, vel) = event {
+        let Coord(x, y) = Coord::from(i);
+        s.pad_down.retain(|&(x0, y0, _)| (x0, y0) != (x, y));
+        s.pad_down.push((x, y, s.page));
+        press(s, l, x, y, vel);
+    }
+
+    // Follow aftertouch while a pad is held, and let go of holds once it's released,
+    // on the page it was pressed on even if another one is showing by now
+    match event {
+        Input::Pressure(i, fr) => {
+            let Coord(x, y) = Coord::from(i);
+            let page = pressed_on(s, x, y);
+            let current = std::mem::replace(&mut s.page, page);
+            aftertouch(s, x, y, Some(fr));
+            s.page = current;
+        }
+        Input::Release(i) => {
+            let Coord(x, y) = Coord::from(i);
+            let page = pressed_on(s, x, y);
+            s.pad_down.retain(|&(x0, y0, _)| (x0, y0) != (x, y));
+            let current = std::mem::replace(&mut s.page, page);
+            aftertouch(s, x, y, None);
+            s.holds.release(page, x, y);
+            s.page = current;
+        }
         _ => {}
     }
+}
 
-    let beat0 = |pd: Pd, s: &mut State, r: Range| match &mut s.beat {
-        Some(ManualBeat { t0, t1, pd0, pd1, r }) => {
+/// Page the pad at `x`, `y` was pressed on, which may not be the one showing now.
+fn pressed_on(s: &State, x: i8, y: i8) -> usize {
+    s.pad_down.iter().find(|&&(x0, y0, _)| (x0, y0) == (x, y)).map_or(s.page, |&(_, _, page)| page)
+}
+
+/// Press the pad at `x`, `y` with velocity `vel` from `0..1`, as if by hand, e.g. for a show cue.
+pub fn press(s: &mut State, l: &mut Lights, x: i8, y: i8, vel: f64) {
+    log::info!("Pad({x}, {y})");
+    s.x = x;
+    s.y = y;
+
+    // Anything but another manual beat, or a hold on top of it, cancels it
+    let cell = s.layout.get(s.page, x, y).copied();
+    if !matches!(cell.map(|c| c.action), Some(Action::Beat0(_) | Action::Beat1(_) | Action::Hold(_))) {
+        s.beat = None;
+    }
+    let Some(cell) = cell else {
+        return;
+    };
+
+    // Flash as bright as the pad was hit
+    let beat0 = |pd: Pd, s: &mut State, range: Range| match &mut s.beat {
+        Some(ManualBeat { t0, pd0, r0, .. }) => {
             *t0 = s.t;
             *pd0 = pd;
+            *r0 = range;
         }
-        None => s.beat = Some(ManualBeat { t0: s.t, t1: 0.0, pd0: pd, pd1: pd, r }),
+        None => s.beat = Some(ManualBeat { t0: s.t, t1: 0.0, pd0: pd, pd1: pd, r0: range, r1: range }),
     };
-    let beat1 = |pd: Pd, s: &mut State, r: Range| match &mut s.beat {
-        Some(ManualBeat { t1, pd1, .. }) => {
+    let beat1 = |pd: Pd, s: &mut State, range: Range| match &mut s.beat {
+        Some(ManualBeat { t1, pd1, r1, .. }) => {
             *t1 = s.t;
             *pd1 = pd;
+            *r1 = range;
         }
-        _ => s.beat = Some(ManualBeat { t0: 0.0, t1: s.t, pd0: pd, pd1: pd, r }),
+        None => s.beat = Some(ManualBeat { t0: 0.0, t1: s.t, pd0: pd, pd1: pd, r0: range, r1: range }),
     };
 
-    // First match on x/y presses only.
-    if let Some((x, y)) = match event {
-        Input::Press(i, _) => Some((Coord::from(i).0, Coord::from(i).1)),
-        _ => None,
-    } {
-        log::info!("Pad({x}, {y})");
-        s.x = x;
-        s.y = y;
-
-        s.phi_mul = 1.0;
-
-        if !(x == 0 && y < 5) && !(x == 7 && y < 5) {
-            s.beat = None;
-        }
-
-        match (x, y) {
-            // Beatmatch
-            (0, 7) => s.bpm_taps.push(s.t),
-            // Beatmatch apply
-            (7, 7) => match s.bpm_taps.len() {
+    match cell.action {
+        Action::Tap => s.tap.tap(s.t),
+        Action::TapApply => {
+            // Taps left over from a while ago are stale, so just reset phase
+            if !s.tap.tapping(s.t) {
+                s.tap.clear();
+            }
+            match s.tap.bpm() {
                 // If no beats, just reset phase
-                0 => s.phi = 0.0,
-                1 => s.bpm_taps.clear(),
-                n => {
-                    // Calculate time difference between each consecutive tap
-                    let dts = s.bpm_taps.drain(..).tuple_windows().map(|(t0, t1)| t1 - t0);
-                    // Average out the difference
-                    let dt = dts.sum::<f64>() / (n as f64 - 1.0);
-                    // Calculate BPM
-                    let bpm = 60.0 / dt;
-
-                    s.phi = 0.0;
-                    s.bpm = bpm;
-                    log::info!("Calculated bpm={bpm:.2} from {n} samples");
+                None if s.tap.is_empty() => {
+                    s.restart_phrase();
+                    s.tapped = Some(s.t);
                 }
-            },
+                None => s.tap.clear(),
+                Some(bpm) => {
+                    s.restart_phrase();
+                    set_bpm(s, bpm);
+                    log::info!("Calculated bpm={bpm:.2} from {} taps", s.tap.len());
+                    s.tap.clear();
+                }
+            }
+        }
+        Action::Beat0(pd) => beat0(pd, s, (vel..0.0).into()),
+        Action::Beat1(pd) => beat1(pd, s, (vel..0.0).into()),
+        Action::Mode(mode) => set_mode(s, mode),
+        Action::Palette(palette) => set_palette(s, palette),
+        Action::Spider(spider) => s.spider = spider,
+        Action::Brightness(brightness) => s.brightness = brightness,
+        // Held until released, in `on_pad()`
+        Action::Hold(layer) => s.holds.press(s.page, x, y, layer),
+    }
+}
 
-            // Manual beats
-            (0, 0) => beat0(Pd(4, 1), s, (1.0..0.0).into()),
-            (0, 1) => beat0(Pd(2, 1), s, (1.0..0.0).into()),
-            (0, 2) => beat0(Pd(1, 1), s, (1.0..0.0).into()),
-            (0, 3) => beat0(Pd(1, 2), s, (1.0..0.0).into()),
-            (0, 4) => beat0(Pd(1, 4), s, (1.0..0.0).into()),
-            (7, 0) => beat1(Pd(4, 1), s, (1.0..0.0).into()),
-            (7, 1) => beat1(Pd(2, 1), s, (1.0..0.0).into()),
-            (7, 2) => beat1(Pd(1, 1), s, (1.0..0.0).into()),
-            (7, 3) => beat1(Pd(1, 2), s, (1.0..0.0).into()),
-            (7, 4) => beat1(Pd(1, 4), s, (1.0..0.0).into()),
-
-            // y=0: Lights off, or a brief pause/break
-            (1, 0) => s.mode = Mode::Off,
-            (1, 0) => s.mode = Mode::Break { beams: Some(BeamPattern::Center) },
-            (2, 0) => s.mode = Mode::Break { beams: Some(BeamPattern::Out) },
-            (3, 0) => s.mode = Mode::RaisingBeams { pd: Pd(4, 1) },
-            (4, 0) => s.mode = Mode::Break { beams: Some(BeamPattern::WaveY) },
-            (5, 0) => s.mode = Mode::Whirl { pd: Pd(16, 1) },
-            (6, 0) => s.mode = Mode::Break { beams: Some(BeamPattern::UpDownWave) },
-
-            // y=1: Solid patterns
-            (1, 1) => s.mode = Mode::On { beams: None },
-            (2, 1) => s.mode = Mode::On { beams: Some(BeamPattern::Twisting) },
-            (3, 1) => s.mode = Mode::On { beams: Some(BeamPattern::Square) },
-            (4, 1) => s.mode = Mode::On { beams: Some(BeamPattern::Whirl) },
-            (5, 1) => s.mode = Mode::On { beams: Some(BeamPattern::SnapX) },
-            (6, 1) => s.mode = Mode::On { beams: Some(BeamPattern::WaveY) },
-
-            (1, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::Square },
-            (2, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::RaisingBeams },
-            (3, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::Twisting },
-            (4, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::Whirl },
-            (5, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::UpDownWave },
-            (6, 2) => s.mode = Mode::AutoBeat { pd: Pd(4, 1), r: (0.2..1.0).into(), beam: BeamPattern::SnapX },
-
-            // y=3: Pd(2, 1) patterns
-            (1, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::Square },
-            (2, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::RaisingBeams },
-            (3, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::Twisting },
-            (4, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::Whirl },
-            (5, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::UpDownWave },
-            (6, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into(), beam: BeamPattern::WaveY },
-
-            // y=4: Pd(1, 1) patterns
-            (1, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::Square },
-            (2, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::RaisingBeams },
-            (3, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::Twisting },
-            (4, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::Whirl },
-            (5, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::UpDownWave },
-            (6, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into(), beam: BeamPattern::WaveY },
-
-            // y=5: Strobes
-            (0, 5) => s.mode = Mode::Strobe0 { pd: Pd(1, 8), duty: 1.0 },
-            (1, 5) => s.mode = Mode::Strobe1 { pd: Pd(1, 8), duty: 1.0 },
-            (2, 5) => s.mode = Mode::Strobe { pd: Pd(1, 4), duty: 1.0 },
-            (3, 5) => s.mode = Mode::Strobe { pd: Pd(1, 8), duty: 1.0 },
-            (4, 5) => s.mode = Mode::Strobe { pd: Pd(1, 8), duty: 1.0 },
-            (5, 5) => s.mode = Mode::ChaseNotColorful { pd: Pd(1, 4) },
-            (6, 5) => s.mode = Mode::Chase { pd: Pd(1, 2), beam: BeamPattern::Twisting },
-            (7, 5) => s.mode = Mode::Chase { pd: Pd(1, 4), beam: BeamPattern::Twisting },
-
-            // y=?: Strobes
-
-            //(2, 0) => s.mode = Mode::
-            // (1, 1) => s.mode = Mode::On,
-            // (1, 2) => s.mode = Mode::Hover,
-            // (1, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into() },
-            // (1, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into() },
-
-            // (6, 0) => s.mode = Mode::Off,
-            // (6, 1) => s.mode = Mode::On,
-            // (6, 2) => s.mode = Mode::Hover,
-            // (6, 3) => s.mode = Mode::AutoBeat { pd: Pd(2, 1), r: (0.2..1.0).into() },
-            // (6, 4) => s.mode = Mode::AutoBeat { pd: Pd(1, 1), r: (0.2..1.0).into() },
-
-            // (7, 5) => s.mode = Mode::AutoBeat { pd: Pd(1, 4), r: (0.0..1.0).into() },
-            // (7, 6) => s.mode = Mode::Strobe { pd: Pd(1, 8), duty: 1.0 },
-            // (0, 5) => s.mode = Mode::AutoBeat { pd: Pd(1, 4), r: (0.0..1.0).into() },
-            // (0, 6) => s.mode = Mode::Strobe { pd: Pd(1, 8), duty: 1.0 },
-            // (6, 7) => s.mode = Mode::Whirl { pd: Pd(16, 1) },
-
-            // slow presets
-            // (3, 0) => {
-            //     s.env(|_| 0.1);
-            //     l.beam_pos = BeamPos::Down;
-            // },
-            // (3, 1) => {},
-            // (3, 2) => {
-            //     s.env(|s| s.phi(1, 1).ramp(1.0).inv().lerp(0.2..0.3));
-            //     l.beam_pos = BeamPos::WaveY { pd: Pd(8, 1) };
-            // },
-
-            // // fast presets
-            // (4, 0) => {
-            //     s.env(|_| 0.4);
-            //     l.beam_pos = BeamPos::WaveY { pd: Pd(8, 1) };
-            // },
-            // (4, 1) => {},
-            // (4, 2) => {
-            //     s.env(|s| s.phi(1, 1).ramp(1.0).inv().lerp(0.2..0.5));
-            //     l.beam_pos = BeamPos::Square { pd: Pd(8, 1) };
-            // },
-
-            // Colorz
-            (1, 7) => s.palette = Palette::Solid(Rgbw::RED),
-            (2, 7) => s.palette = Palette::Split(Rgbw::RED, Rgbw::BLUE),
-            (3, 7) => s.palette = Palette::Split(Rgbw::MAGENTA, Rgbw::RED),
-            (4, 7) => s.palette = Palette::Split(Rgbw::VIOLET, Rgbw::BLUE),
-            (5, 7) => s.palette = Palette::Rainbow,
-            (6, 7) => s.palette = Palette::RainbowOsc,
-
-            (0, 6) => s.palette = Palette::Solid(Rgbw::WHITE),
-            (1, 6) => s.palette = Palette::Solid(Rgbw::BLUE),
-            (2, 6) => s.palette = Palette::Split(Rgbw::BLUE, Rgbw::CYAN),
-            (3, 6) => s.palette = Palette::Split(Rgbw::CYAN, Rgbw::LIME),
-            (4, 6) => s.palette = Palette::Split(Rgbw::MINT, Rgbw::MINT),
-            (5, 6) => s.palette = Palette::Split(Rgbw::LIME, Rgbw::LIME),
-            (6, 6) => s.palette = Palette::RgbOsc,
-            (7, 6) => s.palette = Palette::Solid(Rgbw::RGBW),
-
-            // set(1, 7, Rgb::RED);
-            // set(2, 7, Rgb::RED);
-            // set(3, 7, Rgb::MAGENTA);
-            // set(4, 7, Rgb::PINK);
-            // set(5, 7, Rgb::VIOLET);
-            // set(6, 7, Rgb::hsv(s.phi(16, 1), 1.0, 1.0));
-
-            // set(0, 6, Rgb::WHITE);
-            // set(1, 6, Rgb::BLUE);
-            // set(2, 6, Rgb::BLUE);
-            // set(3, 6, Rgb::CYAN);
-            // set(4, 6, Rgb::CYAN);
-            // set(5, 6, Rgb::MINT);
-            // set(6, 6, Rgb::LIME);
-            // set(7, 6, Rgb::WHITE);
-
-            // hold pressure env
-            // (6, 2) => s.beat0 = Beat::Fr(fr.in_exp()),
-            // (7, 2) => s.beat0 = Beat::Fr(fr.in_exp()),
-
-            // hold mod colors
-            // (5, 1) => {
-            //     s.c_h.hold(x, y, b, Op::f(|s| Rgbw::hsv(s.pd(Pd(4, 1)), 1.0, 1.0)));
-            //     s.env_h.hold(x, y, b, Op::v(1.0));
-            // },
-            // (5, 2) => s.c_h.hold(x, y, b, Op::v(Rgbw::BLACK)),
-            // (5, 3) => {
-            //     s.c_h.hold(x, y, b, Op::v(Rgbw::WHITE));
-            //     s.env_h.hold(x, y, b, Op::v(1.0));
-            // },
-
-            // hold strobe w/ pressure
-            // (6, 3) => s.env_h.hold(x, y, b, Op::f(move |s| s.pd(Pd(1, 4)).square(1.0, fr.in_exp().lerp(1.0..0.5)))),
-            // (7, 3) => s.env_h.hold(x, y, b, Op::f(move |s| s.pd(Pd(1, 8)).square(1.0, fr.in_exp().lerp(1.0..0.5)))),
-
-            // hold white strobe
-            // (6, 4) => {
-            //     s.env_h0.hold(x, y, b, Op::f(move |s| s.pd(Pd(1, 4)).square(1.0, fr.in_exp().lerp(1.0..0.5))));
-            //     s.env_h1.hold(x, y, b, Op::v(0.0));
-            //     s.c_h.hold(x, y, b, Op::v(Rgbw::WHITE));
-            // },
-            // (7, 4) => {
-            //     s.env_h.hold(x, y, b, Op::f(move |s| s.pd(Pd(1, 8)).square(1.0, fr.in_exp().lerp(1.0..0.5))));
-            //     s.c_h.hold(x, y, b, Op::v(Rgbw::WHITE));
-            // },
-
-            // // hold chase
-            // (6, 5) => {
-            //     s.par_src_h.hold(x, y, b, Source::Chase { pd: Pd(1, 1), duty: 0.1 });
-            //     s.env_h1.hold(x, y, b, Op::v(0.0));
-            //     s.c_h.hold(x, y, b, Op::v(Rgbw::WHITE));
-            //     s.strobe_src_h.hold(x, y, b, Source::Strobe { pd: Pd(1, 4), duty: fr.in_exp().lerp(1.0..0.5) });
-            // }
-            _ => {}
+/// Pressing harder on a held strobe pad sharpens the strobe, and on a brightness pad brightens it up to full.
+/// Once released (`fr = None`), they go back to how they were.
+fn aftertouch(s: &mut State, x: i8, y: i8, fr: Option<f64>) {
+    let fr = fr.map(|fr| s.aftertouch_curve.apply(fr));
+    match s.layout.get(s.page, x, y).map(|c| c.action) {
+        Some(Action::Mode(Mode::Strobe { .. } | Mode::Strobe0 { .. } | Mode::Strobe1 { .. })) => s.duty = fr,
+        Some(Action::Brightness(brightness)) => s.brightness = fr.map_or(brightness, |fr| fr.lerp(brightness..1.0)),
+        _ => {}
+    }
+}
+
+/// Show another page of the layout, if there is one.
+fn set_page(s: &mut State, pad: &mut Midi<LaunchpadX>, page: usize) {
+    if page < s.layout.pages() && page != s.page {
+        log::info!("Page {}", s.layout.name(page));
+        s.page = page;
+        // Blank out cells the new page doesn't use
+        pad.send(launchpad_x::Output::Clear);
+    }
+}
+
+///////////////////////// KNOBS /////////////////////////
+
+/// How close a knob needs to get to its parameter's value to pick it up, from `0..1`.
+const TAKEOVER: f64 = 0.03;
+
+/// A live parameter a Launch Control knob can control.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Param {
+    #[default]
+    None,
+    /// Low end of the AutoBeat flash brightness range
+    BeatLo,
+    /// High end of the AutoBeat flash brightness range
+    BeatHi,
+    /// Strobe duty cycle
+    StrobeDuty,
+    /// Offset added to every beam's pitch, centered on the knob
+    BeamPitch,
+    /// Offset added to every beam's yaw, centered on the knob
+    BeamYaw,
+    /// Spider movement speed, from a quarter to 4 times as fast
+    SpiderSpeed,
+    /// Palette hue rotation, once around the color wheel
+    Hue,
+    LaserSize,
+    LaserRotate,
+}
+
+/// Furthest the beam offsets reach either way.
+const BEAM_OFFSET: f64 = 0.25;
+
+impl Param {
+    /// Every parameter which does something.
+    pub const ALL: [Param; 9] = {
+        use Param::*;
+        [
+            BeatLo,
+            BeatHi,
+            StrobeDuty,
+            BeamPitch,
+            BeamYaw,
+            SpiderSpeed,
+            Hue,
+            LaserSize,
+            LaserRotate,
+        ]
+    };
+
+    /// Current value as a knob position from `0..1`, or `None` if it doesn't apply to the current mode.
+    fn get(self, s: &State) -> Option<f64> {
+        match (self, s.mode) {
+            (Param::None, _) => None,
+            (Param::BeatLo, Mode::AutoBeat { r, .. }) => Some(r.lo),
+            (Param::BeatHi, Mode::AutoBeat { r, .. }) => Some(r.hi),
+            (Param::BeatLo | Param::BeatHi, _) => None,
+            (Param::StrobeDuty, Mode::Strobe { duty, .. } | Mode::Strobe0 { duty, .. } | Mode::Strobe1 { duty, .. }) => {
+                Some(duty)
+            }
+            (Param::StrobeDuty, _) => None,
+            (Param::BeamPitch, _) => Some(s.beam_pitch / BEAM_OFFSET * 0.5 + 0.5),
+            (Param::BeamYaw, _) => Some(s.beam_yaw / BEAM_OFFSET * 0.5 + 0.5),
+            (Param::SpiderSpeed, _) => Some((s.spider_speed + 2) as f64 / 4.0),
+            (Param::Hue, _) => Some(s.hue),
+            (Param::LaserSize, _) => Some(s.laser_size),
+            (Param::LaserRotate, _) => Some(s.laser_rotate),
+        }
+    }
+
+    /// The knob position `fr` rounded to a value this can take.
+    fn quantize(self, fr: f64) -> f64 {
+        match self {
+            Param::SpiderSpeed => (fr * 4.0).round() / 4.0,
+            _ => fr,
         }
     }
 
-    // Next match on x/y presses *and* releases, with a bool to indicate which one
-    if let Some((x, y, b)) = match event {
-        Input::Press(i, _) => Some((Coord::from(i).0, Coord::from(i).1, true)),
-        Input::Release(i) => Some((Coord::from(i).0, Coord::from(i).1, false)),
-        _ => None,
-    } {
-        match (x, y) {
+    /// Set from a knob position from `0..1`.
+    fn set(self, s: &mut State, fr: f64) {
+        match (self, &mut s.mode) {
+            (Param::BeatLo, Mode::AutoBeat { r, .. }) => r.lo = fr,
+            (Param::BeatHi, Mode::AutoBeat { r, .. }) => r.hi = fr,
+            (Param::StrobeDuty, Mode::Strobe { duty, .. } | Mode::Strobe0 { duty, .. } | Mode::Strobe1 { duty, .. }) => {
+                *duty = fr
+            }
+            (Param::BeamPitch, _) => s.beam_pitch = (fr - 0.5) * 2.0 * BEAM_OFFSET,
+            (Param::BeamYaw, _) => s.beam_yaw = (fr - 0.5) * 2.0 * BEAM_OFFSET,
+            (Param::SpiderSpeed, _) => s.spider_speed = (fr * 4.0).round() as i8 - 2,
+            (Param::Hue, _) => s.hue = fr,
+            (Param::LaserSize, _) => s.laser_size = fr,
+            (Param::LaserRotate, _) => s.laser_rotate = fr,
             _ => {}
         }
     }
 }
 
+/// What each Launch Control knob controls, by row (send A, send B, pan) and column.
+///
+/// Knobs pick up their parameter once turned to its current value, so values don't jump after
+/// switching modes or turning a knob while it did nothing.
+#[derive(Clone, Debug)]
+pub struct Knobs {
+    pub map: [[Param; 8]; 3],
+    /// Where each knob was last turned to, if it has been
+    last: [[Option<f64>; 8]; 3],
+    /// What each knob last set its parameter to, while it has it picked up.
+    /// Anything else changing the parameter, like switching modes, drops it.
+    caught: [[Option<f64>; 8]; 3],
+}
+
+impl Default for Knobs {
+    fn default() -> Self {
+        use Param::*;
+        Self::new([
+            // The first column shows the brightness fader position, so it's left free
+            [None, BeatLo, BeatHi, StrobeDuty, BeamPitch, BeamYaw, SpiderSpeed, Hue],
+            [None, LaserSize, LaserRotate, None, None, None, None, None],
+            [None; 8],
+        ])
+    }
+}
+
+impl Knobs {
+    pub fn new(map: [[Param; 8]; 3]) -> Self {
+        Self { map, last: Default::default(), caught: Default::default() }
+    }
+
+    /// Whether knob `row`, `col` has picked up its parameter, or `None` if there's nothing for it to control.
+    fn caught(&self, s: &State, row: usize, col: usize) -> Option<bool> {
+        let cur = self.map[row][col].get(s)?;
+        Some(self.caught[row][col] == Some(cur))
+    }
+}
+
+/// Turn knob `row`, `col` to `fr`.
+fn turn(s: &mut State, row: usize, col: u8, fr: f64) {
+    let col = col as usize;
+    if col >= 8 {
+        return;
+    }
+    let param = s.knobs.map[row][col];
+    let last = s.knobs.last[row][col].replace(fr);
+    let caught = s.knobs.caught(s, row, col);
+    let Some(cur) = param.get(s) else {
+        return;
+    };
+
+    // Pick up the parameter when the knob gets close enough, or passes it between messages
+    let close = (param.quantize(fr) - cur).abs() < TAKEOVER;
+    let crossed = last.is_some_and(|last| (last < cur && cur < fr) || (fr < cur && cur < last));
+    if caught == Some(true) || close || crossed {
+        param.set(s, fr);
+        s.knobs.caught[row][col] = param.get(s);
+    }
+}
+
 ///////////////////////// CTRL INPUT /////////////////////////
 
 pub fn on_ctrl(s: &mut State, l: &mut Lights, ctrl: &mut Midi<LaunchControlXL>, input: launch_control_xl::Input) {
@@ -1437,35 +2392,380 @@ pub fn on_ctrl(s: &mut State, l: &mut Lights, ctrl: &mut Midi<LaunchControlXL>,
 
     match input {
         Input::Slider(0, fr) => s.brightness = fr,
+        // Brightness presets, whatever page the pad is on
+        Input::Control(i, true) => s.brightness = BRIGHTNESS[i as usize],
+
+        // Live parameters, see `Knobs`
+        Input::SendA(col, fr) => turn(s, 0, col, fr),
+        Input::SendB(col, fr) => turn(s, 1, col, fr),
+        Input::Pan(col, fr) => turn(s, 2, col, fr),
+
+        // Tempo adjust
+        Input::Left(held) => nudge(s, false, held),
+        Input::Right(held) => nudge(s, true, held),
+        Input::Up(true) => adjust_bpm(s, 0.1),
+        Input::Down(true) => adjust_bpm(s, -0.1),
+        Input::Device(true) => downbeat(s),
+        // Multiplier: back to normal, or triplets
+        Input::Mute(true) => set_mul(s, Multiplier::Normal),
+        Input::Solo(true) => set_mul(s, Multiplier::Triplet),
+
+        // Lasers on/off
+        Input::Focus(0, true) => l.for_each_laser(|laser, i, fr| laser.on = !laser.on),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Capture, Output};
+    use crate::patch::Patch;
 
-        // Input::Slider(1, fr) => s.test0 = fr,
-        // Input::Slider(2, fr) => s.test1 = fr,
-
-        // pattern=LineX
-        // y=0.1
-        // size=0.66
-        //
-
-        // laser tweaks
-        Input::Focus(0, true) => l.laser.on = !l.laser.on,
-
-        // Input::Slider(1, fr) => s.test0 = fr,
-        // Input::Slider(2, fr) => s.test1 = fr,
-        // Input::Slider(3, fr) => s.test2 = fr,
-        // Input::Slider(4, fr) => s.test3 = fr,
-        // Input::Slider(5, fr) => s.test4 = fr,
-        Input::Slider(1, fr) => {
-            l.laser.pattern = LaserPattern::Raw(fr.byte());
-            println!("{:?}", l.laser.pattern);
+    /// A rig with a single strobe at channels 1..=7.
+    fn strobe_rig() -> Lights {
+        let patch = Patch::parse("[[fixture]]\nname = \"strobe\"\ntype = \"strobe\"\naddress = 1").unwrap();
+        Lights::new([10, 16, 4, 1].into(), patch)
+    }
+
+    /// Render a frame at `phi`, and capture the strobe's channels.
+    fn strobe_channels(s: &mut State, l: &mut Lights, phi: f64) -> Vec<u8> {
+        let capture = Capture::new(1);
+        s.phi = phi;
+        render_lights(s, l);
+        l.send(&mut capture.clone());
+        capture.last(1).unwrap()[1..=7].to_vec()
+    }
+
+    #[test]
+    fn strobe_on_off() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.bpm = 120.0;
+        s.brightness = 1.0;
+        s.palette = Palette::Solid(Rgbw(1.0, 0.0, 0.5, 0.0));
+        s.mode = Mode::Strobe { pd: Pd(1, 16), duty: 1.0 };
+
+        // Channel 1 is the dimmer, left at full, then red, green and blue at 2..=4, and 5..=7 stay clear
+        // so the fixture's own strobe and programs don't kick in
+        let on = [255, 255, 0, 127, 0, 0, 0];
+        let off = [255, 0, 0, 0, 0, 0, 0];
+        assert_eq!(strobe_channels(&mut s, &mut l, 0.0), on);
+        assert_eq!(strobe_channels(&mut s, &mut l, 0.0625), off);
+        assert_eq!(strobe_channels(&mut s, &mut l, 0.125), on);
+    }
+
+    #[test]
+    fn phrases() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.bpm = 120.0;
+        s.quantize = Quantize::Phrase;
+        s.next_mode = Some(Launch { val: Mode::Off, page: 0, x: 0, y: 0 });
+
+        // 15.8 beats in, still waiting for the phrase
+        for _ in 0..79 {
+            tick(0.1, &mut s, &mut l);
         }
-        Input::Slider(2, fr) => l.laser.rotate = fr,
-        // Input::Slider(3, fr) => l.laser.x = fr,
-        Input::Slider(4, fr) => l.laser.y = fr,
-        Input::Slider(5, fr) => l.laser.size = fr,
-        Input::Slider(6, fr) => l.laser.color = LaserColor::Raw(fr.byte()),
-
-        Input::Slider(3, fr) => l.laser.xflip = fr,
-        Input::Slider(4, fr) => l.laser.yflip = fr,
-        _ => {}
+        assert_eq!((s.bars, s.phrases), (3, 0));
+        assert!(s.next_mode.is_some());
+
+        // Around the measure, a new phrase launches the queued mode
+        tick(0.1, &mut s, &mut l);
+        tick(0.1, &mut s, &mut l);
+        assert_eq!((s.bars, s.phrases), (4, 1));
+        assert!(s.next_mode.is_none());
+        assert!(matches!(s.mode, Mode::Off));
+    }
+
+    #[test]
+    fn phrase_shifted_over() {
+        let mut s = State::new();
+        s.quantize = Quantize::Phrase;
+        s.shift(15.9);
+        s.next_mode = Some(Launch { val: Mode::Off, page: 0, x: 0, y: 0 });
+
+        // Pulled back, e.g. by a tempo source, nothing is crossed
+        s.shift(-0.5);
+        assert_eq!((s.bars, s.phrases), (3, 0));
+
+        // Pushed forward over the measure, the phrase counts and launches
+        s.shift(0.7);
+        assert_eq!((s.bars, s.phrases), (4, 1));
+        assert!(s.next_mode.is_none());
+
+        // Nudged back over the boundary and forward again, it only counts once
+        s.next_mode = Some(Launch { val: Mode::Off, page: 0, x: 0, y: 0 });
+        s.shift(-0.2);
+        assert_eq!(s.phrases, 1);
+        s.shift(0.2);
+        assert_eq!((s.bars, s.phrases), (4, 1));
+        assert!(s.next_mode.is_some());
+
+        // Reset by hand just after the downbeat, still the same phrase
+        s.restart_phrase();
+        assert_eq!((s.bars, s.phrases, s.phi_beat), (4, 1, 0.0));
+        assert!(s.next_mode.is_some());
+
+        // And partway through, the next one starts now
+        s.shift(9.0);
+        s.restart_phrase();
+        assert_eq!((s.bars, s.phrases, s.phi_beat), (8, 2, 0.0));
+        assert!(s.next_mode.is_none());
+    }
+
+    #[test]
+    fn stale_taps() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.layout = Arc::new(pad_layout().unwrap());
+        s.bpm = 120.0;
+        for t in [10.0, 10.5, 11.0, 11.5] {
+            s.tap.tap(t);
+        }
+
+        // Long after the last tap, applying only resets phase
+        s.t = 100.0;
+        s.phi_beat = 2.5;
+        press(&mut s, &mut l, 7, 7, 1.0);
+        assert_eq!((s.bpm, s.phi_beat), (120.0, 0.0));
+        assert!(s.tap.is_empty());
+    }
+
+    #[test]
+    fn timecode_jump() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.t = 100.0;
+        s.tap.tap(99.5);
+        s.tapped = Some(99.0);
+        s.quantize = Quantize::Bar;
+
+        // Taps stay as long ago as they were, and cues happen right away
+        let cue = Cue {
+            at: 5.0,
+            pad: None,
+            page: 0,
+            mode: Some(Mode::Hover),
+            palette: None,
+            brightness: None,
+        };
+        on_timecode(&mut s, &mut l, 5.0, &[cue]);
+        assert_eq!((s.t, s.tapped, s.tap.last()), (5.0, Some(4.0), Some(4.5)));
+        assert!(matches!(s.mode, Mode::Hover));
+        assert_eq!(s.quantize, Quantize::Bar);
+    }
+
+    #[test]
+    fn ctrl_meter_laser_palette() {
+        use launch_control_xl::types::{Color, Pos};
+        let patch = Patch::parse("[[fixture]]\nname = \"laser\"\ntype = \"laser\"\naddress = 1").unwrap();
+        let (mut s, mut l) = (State::new(), Lights::new([10, 16, 4, 1].into(), patch));
+        let led = |s: &State, l: &Lights, pos| ctrl_leds(s, l).into_iter().find(|&(p, _)| p == pos).unwrap().1;
+
+        // Brightness meter fills up the knobs above the fader, bottom first
+        s.brightness = 0.5;
+        assert_eq!(led(&s, &l, Pos::Pan(0)), Color::Amber(3));
+        assert_eq!(led(&s, &l, Pos::Send(1, 0)), Color::Amber(2));
+        assert_eq!(led(&s, &l, Pos::Send(0, 0)), Color::Off);
+        s.brightness = 1.0;
+        assert_eq!(led(&s, &l, Pos::Send(0, 0)), Color::Amber(3));
+
+        // Laser
+        assert_eq!(led(&s, &l, Pos::Focus(0)), Color::Off);
+        l.for_each_laser(|laser, _, _| laser.on = true);
+        assert_eq!(led(&s, &l, Pos::Focus(0)), Color::Green(3));
+
+        // Only the active palette's group is lit
+        s.palette = Palette::Split(Rgbw::RED, Rgbw::BLUE);
+        let lit = (1..5).filter(|&i| led(&s, &l, Pos::Focus(i)) != Color::Off).collect_vec();
+        assert_eq!(lit, [4]);
+        s.palette = Palette::RgbOsc;
+        let lit = (1..5).filter(|&i| led(&s, &l, Pos::Focus(i)) != Color::Off).collect_vec();
+        assert_eq!(lit, [2]);
+    }
+
+    #[test]
+    fn ctrl_only_changes() {
+        use launch_control_xl::types::{Color, Pos};
+        let (mut s, l) = (State::new(), strobe_rig());
+
+        // At first, only what's lit is sent
+        let leds = ctrl_leds(&s, &l);
+        let sent = ctrl_changes(&[], &leds);
+        assert!(sent.iter().all(|&(_, c)| c != Color::Off));
+        assert!(sent.contains(&(Pos::Focus(1), Color::Amber(3))));
+
+        // Then nothing, until something changes
+        assert_eq!(ctrl_changes(&leds, &ctrl_leds(&s, &l)), []);
+        s.palette = Palette::Solid(Rgbw::RED);
+        let sent = ctrl_changes(&leds, &ctrl_leds(&s, &l));
+        assert_eq!(sent, [(Pos::Focus(1), Color::Off), (Pos::Focus(3), Color::Amber(3))]);
+    }
+
+    #[test]
+    fn layout_checks_cells() {
+        let cell = |x, y| Cell { x, y, action: Action::Tap, swatch: Swatch::Wave, pulse: None };
+        let page = |cells| Page { name: "Test", cells };
+
+        assert!(Layout::new(vec![cell(0, 0)], vec![page(vec![cell(7, 7)]), page(vec![cell(0, 1)])]).is_ok());
+        assert!(pad_layout().is_ok());
+
+        // Twice on one page, or on a page and every page
+        assert!(Layout::new(vec![], vec![page(vec![cell(1, 1), cell(1, 1)])]).is_err());
+        assert!(Layout::new(vec![cell(0, 0)], vec![page(vec![]), page(vec![cell(0, 0)])]).is_err());
+        assert!(Layout::new(vec![cell(0, 0), cell(0, 0)], vec![]).is_err());
+
+        // Off the grid
+        for (x, y) in [(-1, 0), (0, -1), (8, 0), (0, 8)] {
+            assert!(Layout::new(vec![], vec![page(vec![cell(x, y)])]).is_err(), "({x}, {y})");
+            assert!(Layout::new(vec![cell(x, y)], vec![]).is_err(), "({x}, {y})");
+        }
+
+        // More pages than side buttons
+        assert!(Layout::new(vec![], (0..9).map(|_| page(vec![])).collect()).is_err());
+    }
+
+    #[test]
+    fn beat_sides_velocity() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.layout = Arc::new(pad_layout().unwrap());
+
+        // A soft hit on one side doesn't change how hard the other flashes
+        press(&mut s, &mut l, 0, 0, 1.0);
+        press(&mut s, &mut l, 7, 0, 0.2);
+        let beat = s.beat.unwrap();
+        assert_eq!((beat.r0.lo, beat.r1.lo), (1.0, 0.2));
+        press(&mut s, &mut l, 0, 1, 0.5);
+        let beat = s.beat.unwrap();
+        assert_eq!((beat.r0.lo, beat.r1.lo), (0.5, 0.2));
+    }
+
+    #[test]
+    fn aftertouch_curve() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.layout = Arc::new(pad_layout().unwrap());
+        s.page = 4;
+        let brightness = |s: &mut State, curve, fr| {
+            s.aftertouch_curve = curve;
+            aftertouch(s, 3, 6, Some(fr));
+            s.brightness
+        };
+
+        // The brightness pad at 0.3 goes all the way up under a full press, however it's curved
+        for curve in [AftertouchCurve::Linear, AftertouchCurve::Soft, AftertouchCurve::Hard] {
+            assert_eq!(brightness(&mut s, curve, 0.0), 0.3);
+            assert_eq!(brightness(&mut s, curve, 1.0), 1.0);
+        }
+        let half = |s: &mut State, curve| brightness(s, curve, 0.5);
+        assert!(half(&mut s, AftertouchCurve::Hard) < half(&mut s, AftertouchCurve::Linear));
+        assert!(half(&mut s, AftertouchCurve::Linear) < half(&mut s, AftertouchCurve::Soft));
+
+        // Back to the pad's own brightness once let go
+        aftertouch(&mut s, 3, 6, None);
+        assert_eq!(s.brightness, 0.3);
+    }
+
+    #[test]
+    fn holds_stack() {
+        let red = |h: &Holds| h.color.value().map(|c| c.0);
+        let mut holds = Holds::default();
+
+        // Hold red, then white over it. Letting go of white goes back to red
+        holds.press(4, 1, 7, Layer::Color(Rgbw::RED));
+        holds.press(4, 0, 5, Layer::Color(Rgbw::WHITE));
+        holds.press(4, 1, 5, Layer::Blackout);
+        assert_eq!(red(&holds), Some(0.0));
+        holds.release(4, 0, 5);
+        assert_eq!(red(&holds), Some(1.0));
+
+        // Letting go underneath leaves the top alone
+        holds.press(4, 0, 5, Layer::Color(Rgbw::WHITE));
+        holds.release(4, 1, 7);
+        assert_eq!(red(&holds), Some(0.0));
+        holds.release(4, 0, 5);
+        assert_eq!(red(&holds), None);
+
+        // Other kinds are held separately
+        assert!(holds.dimmer.value().is_some());
+        holds.release(4, 1, 5);
+        assert!(holds.dimmer.value().is_none());
+    }
+
+    #[test]
+    fn learned_holds_by_page() {
+        let (mut s, mut l) = (State::new(), strobe_rig());
+        s.layout = Arc::new(pad_layout().unwrap());
+        let held = |s: &State| s.holds.color.value().is_some();
+
+        // Held from the FX page, while the pad shows the main page
+        on_learned(&mut s, &mut l, Target::Pad { page: 4, x: 1, y: 7 }, 1.0);
+        assert!(held(&s));
+        assert_eq!(s.page, 0);
+
+        // Another control at the same spot on another page doesn't let go of it
+        on_learned(&mut s, &mut l, Target::Pad { page: 0, x: 1, y: 7 }, 1.0);
+        on_learned(&mut s, &mut l, Target::Pad { page: 0, x: 1, y: 7 }, 0.0);
+        assert!(held(&s));
+        on_learned(&mut s, &mut l, Target::Pad { page: 4, x: 1, y: 7 }, 0.0);
+        assert!(!held(&s));
+    }
+
+    #[test]
+    fn knob_pickup() {
+        let mut s = State::new();
+        s.hue = 0.5;
+
+        // Nothing happens until the knob gets to the hue
+        turn(&mut s, 0, 7, 0.2);
+        turn(&mut s, 0, 7, 0.3);
+        assert_eq!((s.hue, s.knobs.caught(&s, 0, 7)), (0.5, Some(false)));
+        turn(&mut s, 0, 7, 0.49);
+        assert_eq!((s.hue, s.knobs.caught(&s, 0, 7)), (0.49, Some(true)));
+
+        // Then follows it however far it jumps
+        turn(&mut s, 0, 7, 0.9);
+        turn(&mut s, 0, 7, 0.1);
+        assert_eq!(s.hue, 0.1);
+
+        // Until something else changes it
+        s.hue = 0.5;
+        turn(&mut s, 0, 7, 0.2);
+        assert_eq!((s.hue, s.knobs.caught(&s, 0, 7)), (0.5, Some(false)));
+    }
+
+    #[test]
+    fn knob_fast_turn() {
+        let mut s = State::new();
+        s.mode = Mode::Strobe { pd: Pd(1, 4), duty: 0.5 };
+
+        // Turned past the duty between messages, it's picked up
+        turn(&mut s, 0, 3, 0.1);
+        turn(&mut s, 0, 3, 0.8);
+        assert!(matches!(s.mode, Mode::Strobe { duty, .. } if duty == 0.8));
+
+        // Another strobe has its own duty to pick up
+        s.mode = Mode::Strobe { pd: Pd(1, 8), duty: 0.2 };
+        assert_eq!(s.knobs.caught(&s, 0, 3), Some(false));
+        turn(&mut s, 0, 3, 0.9);
+        assert!(matches!(s.mode, Mode::Strobe { duty, .. } if duty == 0.2));
+
+        // And no duty at all outside a strobe
+        s.mode = Mode::Off;
+        assert_eq!(s.knobs.caught(&s, 0, 3), None);
+    }
+
+    #[test]
+    fn knob_spider_speed() {
+        let mut s = State::new();
+
+        // Picked up anywhere within the current step, then steps along with the knob
+        turn(&mut s, 0, 6, 0.1);
+        turn(&mut s, 0, 6, 0.3);
+        assert_eq!(s.spider_speed, 0);
+        turn(&mut s, 0, 6, 0.55);
+        assert_eq!((s.spider_speed, s.knobs.caught(&s, 0, 6)), (0, Some(true)));
+        turn(&mut s, 0, 6, 0.7);
+        assert_eq!(s.spider_speed, 1);
+        turn(&mut s, 0, 6, 1.0);
+        assert_eq!(s.spider_speed, 2);
+        turn(&mut s, 0, 6, 0.0);
+        assert_eq!(s.spider_speed, -2);
     }
 }
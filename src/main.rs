@@ -5,11 +5,13 @@
 #![feature(stmt_expr_attributes)]
 
 use anyhow::Result;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
 use itertools::Itertools;
 use rand::seq::SliceRandom;
 use stagebridge::color::{Rgb, Rgbw};
 use stagebridge::dmx::device::laser_scan_30w::{LaserColor, LaserPattern};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 use std::{thread, time::Duration};
 
@@ -21,13 +23,33 @@ use stagebridge::midi::device::{
 use stagebridge::midi::Midi;
 use stagebridge::prelude::*;
 
+mod artnet;
+mod audio;
+mod clock;
+mod config;
+mod e131;
+mod engine;
 mod gui;
+mod learn;
 mod lights;
+mod link;
 mod logic;
+mod midi;
+mod output;
+mod patch;
+mod record;
+mod tap;
+mod timecode;
 mod utils;
 
+use config::Config;
+use engine::{Chase, Engine, TempoSync};
+use learn::Learn;
 use lights::Lights;
 use logic::State;
+use midi::Controller;
+use output::{Capture, Protocol};
+use patch::Patch;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -35,6 +57,122 @@ struct Args {
     /// Log verbosity. Add more v's for more verbosity.
     #[arg(short, action = ArgAction::Count)]
     verbose: u8,
+
+    /// Path to the config file. Defaults to `mslive.toml`, if it exists.
+    #[arg(long)]
+    config: Option<std::path::PathBuf>,
+
+    /// Path to the fixture patch file.
+    #[arg(long, default_value = "patch.toml")]
+    patch: std::path::PathBuf,
+
+    /// Default DMX destination, for universes without one in the patch.
+    #[arg(long)]
+    dest: Option<std::net::IpAddr>,
+
+    /// DMX destination UDP port. Defaults to the protocol's standard port.
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// DMX output protocol. Defaults to E1.31.
+    #[arg(long, value_enum)]
+    protocol: Option<Protocol>,
+
+    /// Launchpad X MIDI port name, or a prefix or substring of it.
+    #[arg(long)]
+    pad: Option<String>,
+
+    /// Launch Control XL MIDI port name, or a prefix or substring of it.
+    #[arg(long)]
+    ctrl: Option<String>,
+
+    /// How hard a held Launchpad pad needs to be pressed to send aftertouch. Defaults to medium.
+    #[arg(long, value_enum)]
+    aftertouch: Option<logic::Aftertouch>,
+
+    /// How aftertouch maps onto a held strobe's duty or brightness pad, once past the threshold. Defaults to linear.
+    #[arg(long, value_enum)]
+    aftertouch_curve: Option<logic::AftertouchCurve>,
+
+    /// File to save MIDI learn bindings to. Defaults to `bindings.toml`.
+    #[arg(long)]
+    bindings: Option<std::path::PathBuf>,
+
+    /// MIDI port to follow clock from, or `virtual` to open a new port.
+    #[arg(long)]
+    clock_in: Option<String>,
+
+    /// MIDI port to send clock to, or `virtual` to open a new port.
+    #[arg(long)]
+    clock_out: Option<String>,
+
+    /// Round tapped tempos to a whole or half BPM.
+    #[arg(long, value_enum)]
+    tap_snap: Option<tap::Snap>,
+
+    /// Number of beats to glide over to a tapped or adjusted tempo.
+    #[arg(long)]
+    glide: Option<f64>,
+
+    /// Hold mode and palette changes until the next beat, bar or phrase.
+    #[arg(long, value_enum)]
+    quantize: Option<logic::Quantize>,
+
+    /// Join the Ableton Link session on the local network.
+    #[arg(long)]
+    link: bool,
+
+    /// Follow the beat of an audio capture device (by name, or `default`) or a .wav/.flac file.
+    #[arg(long)]
+    audio: Option<String>,
+
+    /// MIDI port to chase timecode on, or `virtual` to open a new port.
+    #[arg(long, conflicts_with = "ltc")]
+    mtc: Option<String>,
+
+    /// Chase linear timecode from an audio capture device (by name, or `default`) or a .wav/.flac file.
+    #[arg(long)]
+    ltc: Option<String>,
+
+    /// Show file of cues to run as the timecode passes them.
+    #[arg(long)]
+    show: Option<std::path::PathBuf>,
+
+    /// Lighting engine and DMX output rate in Hz.
+    #[arg(long, default_value_t = 44.0)]
+    rate: f64,
+
+    /// Run without a window, e.g. on a machine with no display.
+    #[arg(long)]
+    headless: bool,
+
+    /// Render everything as usual, but don't send any DMX.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Record every DMX frame sent to this file, for `replay`.
+    #[arg(long)]
+    record: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a recording made with `--record` back out at its original timing.
+    Replay {
+        /// Recording to play.
+        file: std::path::PathBuf,
+
+        /// Playback speed, e.g. 2.0 for double speed.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Play the recording over and over.
+        #[arg(long = "loop")]
+        looping: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -54,50 +192,145 @@ fn main() -> Result<()> {
         .parse_default_env()
         .init();
 
-    Midi::<LaunchpadX>::list();
+    // Load the config and fixture patch before touching any hardware, so mistakes fail fast.
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None if std::path::Path::new("mslive.toml").exists() => Config::load("mslive.toml")?,
+        None => Config::default(),
+    };
 
-    // Initialize input devices
-    // let mut pad = Midi::new("WIDI Uhost", LaunchpadX::default());
-    let mut pad = Midi::new("Launchpad X:Launchpad X LPX MIDI", LaunchpadX::default());
-    let mut ctrl = Midi::new("Launch Control XL:Launch Control XL", LaunchControlXL);
-    {
-        use launchpad_x::{types::*, *};
-        pad.send(Output::Pressure(Pressure::Off, PressureCurve::Medium));
-        pad.send(Output::Brightness(1.0));
+    let protocol = args.protocol.or(config.dmx.protocol).unwrap_or_default();
+    let port = args.port.or(config.dmx.port);
+
+    // Replaying only needs somewhere to send the frames.
+    if let Some(Command::Replay { file, speed, looping }) = &args.command {
+        let frames = record::load(file)?;
+        log::info!("Replaying {} frames from {} over {protocol:?}", frames.len(), file.display());
+        return record::replay(&frames, &mut *protocol.open(port)?, *speed, *looping);
     }
 
+    let patch = Patch::load(&args.patch)?;
+
+    // Initialize input devices
+    let pad_name = args.pad.or(config.midi.pad).unwrap_or("Launchpad X LPX MIDI".into());
+    let ctrl_name = args.ctrl.or(config.midi.ctrl).unwrap_or("Launch Control XL".into());
+    let aftertouch = args.aftertouch.or(config.midi.aftertouch).unwrap_or_default();
+    let setup_pad = move |pad: &mut _| logic::setup_pad(pad, aftertouch);
+    let pad = Controller::new(pad_name, |port| Midi::new(port, LaunchpadX::default()), setup_pad);
+    let ctrl = Controller::new(ctrl_name, |port| Midi::new(port, LaunchControlXL), logic::setup_ctrl);
+    let learn = Learn::load(args.bindings.or(config.midi.bindings).unwrap_or("bindings.toml".into()))?;
+    let requests = learn.requests();
+
     // Connect to our lighting rig's Arduino DMX adapter.
-    let mut lights = Lights::new("10.16.4.1".parse()?)?;
+    let dest = args.dest.or(config.dmx.dest).unwrap_or("10.16.4.1".parse()?);
+    let mut output: Box<dyn output::Output> = match args.dry_run {
+        true => {
+            log::info!("Dry run, not sending any DMX");
+            Box::new(Capture::new(1))
+        }
+        false => {
+            log::info!("Sending DMX over {protocol:?} to {dest}");
+            protocol.open(port)?
+        }
+    };
+    if let Some(path) = &args.record {
+        output = Box::new(record::Recorder::new(output, path)?);
+    }
+    let lights = Lights::new(dest, patch);
+
+    // Follow the music, if asked to
+    let audio = match args.audio.or(config.audio.source) {
+        Some(source) => {
+            let default = audio::Settings::default();
+            let settings = audio::Settings {
+                confidence: config.audio.confidence.unwrap_or(default.confidence),
+                tap_override: config.audio.tap_override.unwrap_or(default.tap_override),
+                min_bpm: config.audio.min_bpm.unwrap_or(default.min_bpm),
+                max_bpm: config.audio.max_bpm.unwrap_or(default.max_bpm),
+            };
+            anyhow::ensure!(
+                0.0 < settings.min_bpm && settings.min_bpm < settings.max_bpm,
+                "Invalid audio tempo range {}..{}",
+                settings.min_bpm,
+                settings.max_bpm
+            );
+            Some(audio::Audio::spawn(audio::Source::parse(&source), settings)?)
+        }
+        None => None,
+    };
 
     // Initialize main state
     let mut state = State::new();
+    state.layout = Arc::new(logic::pad_layout()?);
+    state.tap = tap::TapTempo::new(args.tap_snap.or(config.tap.snap).unwrap_or_default());
+    state.glide_beats = args.glide.or(config.tap.glide).unwrap_or(4.0);
+    state.quantize = args.quantize.or(config.launch.quantize).unwrap_or_default();
+    state.aftertouch_curve = args.aftertouch_curve.or(config.midi.aftertouch_curve).unwrap_or_default();
+    let rows = [config.knobs.send_a, config.knobs.send_b, config.knobs.pan];
+    for (row, params) in state.knobs.map.iter_mut().zip(rows) {
+        if let Some(params) = params {
+            *row = params;
+        }
+    }
 
-    // Start the main loop, managed by the OS's windowing system.
-    let mut last = Instant::now();
-    eframe::run_simple_native("mslive", Default::default(), move |ctx, _frame| {
-        let elapsed = last.elapsed();
-        last = Instant::now();
+    let clock_in = args.clock_in.or(config.midi.clock_in).map(|name| clock::ClockIn::open(&name)).transpose()?;
+    let clock_out = args
+        .clock_out
+        .or(config.midi.clock_out)
+        .map(|name| clock::ClockOut::open(&name, state.bpm))
+        .transpose()?;
+    let link = (args.link || config.link.enabled).then(|| link::Link::join(state.bpm));
+    let tempo = TempoSync { audio, clock_in, clock_out, link };
 
-        let (s, l) = (&mut state, &mut lights);
+    // Chase timecode, if asked to. Given on the command line, either kind replaces the configured one.
+    let freewheel = config.timecode.freewheel.unwrap_or(2.0);
+    anyhow::ensure!(freewheel >= 0.0, "Timecode freewheel must not be negative");
+    let (mtc, ltc) = match (args.mtc, args.ltc) {
+        (None, None) => (config.timecode.mtc, config.timecode.ltc),
+        given => given,
+    };
+    let input = match (mtc, ltc) {
+        (Some(_), Some(_)) => anyhow::bail!("Chase either MIDI or linear timecode, not both"),
+        (Some(name), None) => Some(timecode::TimecodeIn::mtc(&name, freewheel)?),
+        (None, Some(source)) => Some(timecode::TimecodeIn::ltc(audio::Source::parse(&source), freewheel)?),
+        (None, None) => None,
+    };
+    let show = args
+        .show
+        .or(config.timecode.show)
+        .map(|path| timecode::Show::load(path, &state.layout))
+        .transpose()?;
+    let chase = match (input, show) {
+        (Some(input), show) => Some(Chase::new(input, show.unwrap_or_default())),
+        (None, Some(_)) => anyhow::bail!("A show needs timecode to run along with, set --mtc or --ltc"),
+        (None, None) => None,
+    };
 
-        // Update logic
-        {
-            for input in ctrl.recv() {
-                logic::on_ctrl(s, l, &mut ctrl, input);
-            }
-            for input in pad.recv() {
-                logic::on_pad(s, l, &mut pad, input);
-            }
+    anyhow::ensure!(args.rate > 0.0, "--rate must be positive");
+    let mut engine = Engine::new(state, lights, output, pad, ctrl, learn, tempo, chase);
 
-            logic::tick(elapsed.as_secs_f64(), s, l);
+    if args.headless {
+        // Run the engine right here until Ctrl-C, then leave the rig dark.
+        let running = Arc::new(AtomicBool::new(true));
+        let r = Arc::clone(&running);
+        ctrlc::set_handler(move || r.store(false, Ordering::Relaxed))?;
 
-            logic::render_lights(s, l);
-            logic::render_pad(s, &mut pad);
-            logic::render_ctrl(s, &mut ctrl);
-        }
+        log::info!("Running headless, press Ctrl-C to exit");
+        engine.run(args.rate, &running, |_| {});
+
+        log::info!("Shutting down");
+        engine.blackout();
+        return Ok(());
+    }
 
-        // Always render the GUI each frame
-        gui::render_gui(s, l, ctx);
+    // Run the lighting engine on its own thread, so DMX output doesn't depend on the window.
+    let snapshot = engine.spawn(args.rate);
+
+    // Start the GUI loop, managed by the OS's windowing system.
+    eframe::run_simple_native("mslive", Default::default(), move |ctx, _frame| {
+        // Draw whatever the engine last rendered
+        let snap = snapshot.lock().unwrap().clone();
+        gui::render_gui(&snap, ctx, &requests);
 
         // Immediately request a repaint again from the OS to render at maximum speed.
         ctx.request_repaint();
@@ -0,0 +1,111 @@
+use anyhow::Result;
+use midir::MidiInput;
+use stagebridge::midi::device::launchpad_x::LaunchpadX;
+use stagebridge::midi::Midi;
+use std::time::{Duration, Instant};
+
+/// How often to check whether a controller was plugged in or unplugged.
+const POLL: Duration = Duration::from_secs(1);
+
+/// Names of all available MIDI input ports.
+pub fn ports() -> Result<Vec<String>> {
+    let input = MidiInput::new("mslive")?;
+    Ok(input.ports().iter().filter_map(|p| input.port_name(p).ok()).collect())
+}
+
+/// Whether `port` is one `find` could pick for `name`.
+pub fn matches(port: &str, name: &str) -> bool {
+    port.starts_with(name) || port.to_lowercase().contains(&name.to_lowercase())
+}
+
+/// The device a port is on: the client name before the `:` on Linux, e.g. `Launchpad X` for
+/// `Launchpad X:Launchpad X LPX DAW 20:0`, or the whole name elsewhere.
+pub fn device(port: &str) -> &str {
+    port.split_once(':').map_or(port, |(device, _)| device)
+}
+
+/// Find the port best matching `name`: an exact match, then a prefix, then a case-insensitive substring.
+pub fn find<'a>(ports: &'a [String], name: &str) -> Option<&'a String> {
+    let lower = name.to_lowercase();
+    ports
+        .iter()
+        .find(|p| *p == name)
+        .or_else(|| ports.iter().find(|p| p.starts_with(name)))
+        .or_else(|| ports.iter().find(|p| p.to_lowercase().contains(&lower)))
+}
+
+/// A MIDI controller which may be missing, or come and go during a show.
+///
+/// Generic over the connection type `M`, usually a `Midi<Device>`.
+pub struct Controller<M> {
+    /// Configured port name, or a prefix or substring of it
+    name: String,
+    /// Full name of the connected port, and the connection itself
+    conn: Option<(String, M)>,
+    /// Opens a connection to a port
+    open: fn(&str) -> M,
+    /// Sets up a freshly opened connection
+    setup: Box<dyn Fn(&mut M) + Send>,
+    /// When the ports were last checked
+    polled: Instant,
+}
+
+impl<M> Controller<M> {
+    pub fn new(name: String, open: fn(&str) -> M, setup: impl Fn(&mut M) + Send + 'static) -> Self {
+        let mut ctrl = Self { name, conn: None, open, setup: Box::new(setup), polled: Instant::now() };
+        ctrl.check();
+
+        if ctrl.conn.is_none() {
+            Midi::<LaunchpadX>::list();
+            log::warn!("No MIDI port matches `{}` (see the available ports above), will keep looking", ctrl.name);
+        }
+        ctrl
+    }
+
+    /// Connect or disconnect if the controller was plugged in or unplugged since the last check.
+    pub fn poll(&mut self) {
+        if self.polled.elapsed() >= POLL {
+            self.check();
+        }
+    }
+
+    fn check(&mut self) {
+        self.polled = Instant::now();
+
+        let ports = match ports() {
+            Ok(ports) => ports,
+            Err(e) => return log::debug!("Failed to list MIDI ports: {e}"),
+        };
+
+        match &self.conn {
+            Some((port, _)) if !ports.contains(port) => {
+                log::warn!("MIDI port `{port}` disconnected");
+                self.conn = None;
+            }
+            Some(_) => {}
+            None => {
+                if let Some(port) = find(&ports, &self.name) {
+                    log::info!("MIDI port `{port}` connected for `{}`", self.name);
+                    let mut midi = (self.open)(port);
+                    (self.setup)(&mut midi);
+                    self.conn = Some((port.clone(), midi));
+                }
+            }
+        }
+    }
+
+    /// The connection, if the controller is plugged in.
+    pub fn get(&mut self) -> Option<&mut M> {
+        self.conn.as_mut().map(|(_, midi)| midi)
+    }
+
+    /// Configured port name, or a prefix or substring of it.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Full name of the connected port, if the controller is plugged in.
+    pub fn port(&self) -> Option<&str> {
+        self.conn.as_ref().map(|(port, _)| port.as_str())
+    }
+}
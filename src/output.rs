@@ -0,0 +1,154 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::artnet::ArtNet;
+use crate::e131::E131;
+
+/// A transport for DMX frames.
+pub trait Output: Send {
+    /// Send a DMX buffer, starting with the start code, to `universe` on the receiver at `addr`.
+    fn send(&mut self, addr: &IpAddr, universe: u16, dmx: &[u8]) -> Result<()>;
+}
+
+/// Which protocol to speak to the DMX nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// E1.31 (sACN) over UDP 5568
+    #[default]
+    E131,
+    /// Art-Net ArtDmx over UDP 6454
+    #[serde(rename = "artnet")]
+    #[value(name = "artnet")]
+    ArtNet,
+}
+
+impl Protocol {
+    /// Open an output speaking this protocol, sending to `port` or the protocol's standard port.
+    pub fn open(self, port: Option<u16>) -> Result<Box<dyn Output>> {
+        Ok(match self {
+            Protocol::E131 => Box::new(E131::new(port.unwrap_or(crate::e131::PORT))?),
+            Protocol::ArtNet => Box::new(ArtNet::new(port.unwrap_or(crate::artnet::PORT))?),
+        })
+    }
+}
+
+/// A DMX buffer as it would have been sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sent {
+    pub addr: IpAddr,
+    pub universe: u16,
+    /// DMX buffer, starting with the start code
+    pub dmx: Vec<u8>,
+}
+
+/// An output which keeps the last few buffers in memory instead of sending them anywhere.
+///
+/// Clones share the same buffers, so keep one around to inspect what was sent through another.
+#[derive(Clone)]
+pub struct Capture {
+    sent: Arc<Mutex<VecDeque<Sent>>>,
+    /// Maximum number of buffers to keep
+    n: usize,
+}
+
+impl Capture {
+    pub fn new(n: usize) -> Self {
+        Self { sent: Default::default(), n }
+    }
+
+    /// Every buffer kept, oldest first.
+    pub fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// The most recent buffer sent to `universe`.
+    pub fn last(&self, universe: u16) -> Option<Vec<u8>> {
+        self.sent.lock().unwrap().iter().rev().find(|s| s.universe == universe).map(|s| s.dmx.clone())
+    }
+
+    /// Forget every buffer kept so far.
+    pub fn clear(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+}
+
+impl Output for Capture {
+    fn send(&mut self, addr: &IpAddr, universe: u16, dmx: &[u8]) -> Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        sent.push_back(Sent { addr: *addr, universe, dmx: dmx.to_vec() });
+        while sent.len() > self.n {
+            sent.pop_front();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    /// Send `dmx` to `universe` through `protocol`, and receive the packet on a local listener.
+    fn roundtrip(protocol: Protocol, universe: u16, dmx: &[u8]) -> Vec<u8> {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut output = protocol.open(Some(port)).unwrap();
+        output.send(&[127, 0, 0, 1].into(), universe, dmx).unwrap();
+
+        let mut buf = [0u8; 1024];
+        let n = listener.recv(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn capture_keeps_last_n() {
+        let addr = [10, 16, 4, 1].into();
+        for n in 0..3 {
+            let capture = Capture::new(n);
+            let mut output = capture.clone();
+            for i in 0..5 {
+                output.send(&addr, 1, &[0, i]).unwrap();
+            }
+            let kept: Vec<_> = capture.sent().iter().map(|s| s.dmx[1]).collect();
+            assert_eq!(kept, (5 - n as u8..5).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn e131_over_udp() {
+        let dmx = [0, 1, 2, 3, 255];
+        let p = roundtrip(Protocol::E131, 7, &dmx);
+
+        assert_eq!(p.len(), 125 + dmx.len());
+        assert_eq!(&p[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(p[18..22], 4u32.to_be_bytes()); // VECTOR_ROOT_E131_DATA
+        assert_eq!(p[40..44], 2u32.to_be_bytes()); // VECTOR_E131_DATA_PACKET
+        assert_eq!(p[111], 0); // sequence
+        assert_eq!(p[113..115], 7u16.to_be_bytes());
+        assert_eq!(p[123..125], (dmx.len() as u16).to_be_bytes());
+        assert_eq!(p[125..], dmx);
+    }
+
+    #[test]
+    fn artnet_over_udp() {
+        let dmx = [0, 1, 2, 3, 255];
+        let p = roundtrip(Protocol::ArtNet, 7, &dmx);
+
+        assert_eq!(&p[..8], b"Art-Net\0");
+        assert_eq!(p[8..10], 0x5000u16.to_le_bytes()); // ArtDmx
+        assert_eq!(p[10..12], 14u16.to_be_bytes());
+        assert_eq!(p[12], 1); // sequence
+        assert_eq!(p[14..16], 6u16.to_le_bytes()); // universe 7 is port address 6
+
+        // Without the start code, padded to an even length
+        assert_eq!(p[16..18], 4u16.to_be_bytes());
+        assert_eq!(p[18..], [1, 2, 3, 255]);
+    }
+}
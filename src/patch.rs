@@ -0,0 +1,180 @@
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Highest addressable DMX channel in a universe.
+pub const CHANNELS: usize = 512;
+
+/// Valid E1.31 universe numbers.
+pub const UNIVERSES: std::ops::RangeInclusive<u16> = 1..=63999;
+
+/// Fixture types we know how to encode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureKind {
+    Par,
+    Beam,
+    Bar,
+    Spider,
+    Strobe,
+    Laser,
+}
+
+impl FixtureKind {
+    /// Number of DMX channels the fixture occupies.
+    pub fn footprint(self) -> usize {
+        match self {
+            FixtureKind::Par => 8,
+            FixtureKind::Beam => 15,
+            FixtureKind::Bar => 7,
+            FixtureKind::Spider => 15,
+            FixtureKind::Strobe => 7,
+            FixtureKind::Laser => 11,
+        }
+    }
+}
+
+/// A single patched fixture.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    /// Logical name, e.g. `par0` or `laser`
+    pub name: String,
+    /// Fixture type
+    #[serde(rename = "type")]
+    pub kind: FixtureKind,
+    /// E1.31 universe number
+    #[serde(default = "default_universe")]
+    pub universe: u16,
+    /// DMX start address, from `1..=512`
+    pub address: usize,
+}
+
+fn default_universe() -> u16 {
+    1
+}
+
+impl Fixture {
+    /// Last DMX channel occupied by this fixture.
+    pub fn end(&self) -> usize {
+        self.address + self.kind.footprint() - 1
+    }
+}
+
+/// Where to send a universe.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Universe {
+    /// E1.31 universe number
+    pub universe: u16,
+    /// IP address of the node driving this universe
+    pub dest: IpAddr,
+}
+
+/// The rig's fixture patch, loaded from a TOML file.
+///
+/// Fixtures of the same type are indexed in the order they appear in the file,
+/// so the first `par` is `Lights::pars[0]`, the second is `Lights::pars[1]`, and so on.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patch {
+    /// Universe destinations. Universes not listed here are sent to the default destination.
+    #[serde(rename = "universe", default)]
+    pub universes: Vec<Universe>,
+    #[serde(rename = "fixture", default)]
+    pub fixtures: Vec<Fixture>,
+}
+
+impl Patch {
+    /// Load and validate a patch file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read patch {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid patch {}", path.display()))
+    }
+
+    /// Parse and validate a patch from a TOML string.
+    pub fn parse(text: &str) -> Result<Self> {
+        let patch: Patch = toml::from_str(text)?;
+        patch.validate()?;
+        Ok(patch)
+    }
+
+    /// Number of fixtures of the given type.
+    pub fn count(&self, kind: FixtureKind) -> usize {
+        self.fixtures.iter().filter(|f| f.kind == kind).count()
+    }
+
+    /// Iterate through the fixtures, with an additional index among fixtures of the same type.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Fixture)> {
+        let fixtures = &self.fixtures;
+        fixtures.iter().enumerate().map(|(i, f)| (fixtures[..i].iter().filter(|g| g.kind == f.kind).count(), f))
+    }
+
+    /// Sorted universe numbers which have at least one fixture patched.
+    pub fn universes(&self) -> Vec<u16> {
+        self.fixtures.iter().map(|f| f.universe).sorted().dedup().collect()
+    }
+
+    /// Destination for a universe, if one is configured.
+    pub fn dest(&self, universe: u16) -> Option<IpAddr> {
+        self.universes.iter().find(|u| u.universe == universe).map(|u| u.dest)
+    }
+
+    /// Size of a DMX buffer for a universe, including the start code, large enough to hold every fixture in it.
+    pub fn size(&self, universe: u16) -> usize {
+        1 + self.fixtures.iter().filter(|f| f.universe == universe).map(|f| f.end()).max().unwrap_or(0)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (i, u) in self.universes.iter().enumerate() {
+            ensure!(UNIVERSES.contains(&u.universe), "Universe {} is outside of the valid range 1..=63999", u.universe);
+            ensure!(
+                self.universes[..i].iter().all(|v| v.universe != u.universe),
+                "Universe {} is listed more than once",
+                u.universe
+            );
+        }
+
+        for f in &self.fixtures {
+            ensure!(!f.name.is_empty(), "Fixture at address {} has an empty name", f.address);
+            ensure!(
+                UNIVERSES.contains(&f.universe),
+                "Fixture `{}` is in universe {}, which is outside of the valid range 1..=63999",
+                f.name,
+                f.universe
+            );
+            ensure!(
+                (1..=CHANNELS).contains(&f.address) && f.end() <= CHANNELS,
+                "Fixture `{}` at channels {}..={} is outside of the valid range 1..={CHANNELS}",
+                f.name,
+                f.address,
+                f.end()
+            );
+        }
+
+        for (i, a) in self.fixtures.iter().enumerate() {
+            for b in &self.fixtures[..i] {
+                if a.name == b.name {
+                    bail!("Fixture name `{}` is used more than once", a.name);
+                }
+                if a.universe == b.universe && a.address <= b.end() && b.address <= a.end() {
+                    bail!(
+                        "Fixture `{}` at channels {}..={} overlaps `{}` at channels {}..={} in universe {}",
+                        a.name,
+                        a.address,
+                        a.end(),
+                        b.name,
+                        b.address,
+                        b.end(),
+                        a.universe
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
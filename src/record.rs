@@ -0,0 +1,311 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::output::Output;
+
+/// Identifies a recording, followed by the format version.
+const MAGIC: &[u8; 4] = b"MSLV";
+const VERSION: u8 = 1;
+
+/// How often to flush a recording to disk.
+const FLUSH: Duration = Duration::from_secs(1);
+
+/// A single recorded DMX buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Time since the start of the recording
+    pub t: Duration,
+    /// Receiver the buffer was sent to
+    pub addr: IpAddr,
+    /// Universe the buffer was sent to
+    pub universe: u16,
+    /// DMX buffer, starting with the start code
+    pub dmx: Vec<u8>,
+}
+
+/// Writes frames in the recording format.
+///
+/// Each frame is stored as its timestamp in microseconds (u64), the address (a 4 or 6 tag byte,
+/// then 4 or 16 bytes), the universe (u16), and the buffer length (u16) followed by the buffer.
+/// All integers are little endian.
+pub struct Writer<W: Write> {
+    w: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut w: W) -> Result<Self> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        Ok(Self { w })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> Result<()> {
+        let w = &mut self.w;
+        w.write_all(&(frame.t.as_micros() as u64).to_le_bytes())?;
+        match frame.addr {
+            IpAddr::V4(ip) => {
+                w.write_all(&[4])?;
+                w.write_all(&ip.octets())?;
+            }
+            IpAddr::V6(ip) => {
+                w.write_all(&[6])?;
+                w.write_all(&ip.octets())?;
+            }
+        }
+        w.write_all(&frame.universe.to_le_bytes())?;
+        w.write_all(&(frame.dmx.len() as u16).to_le_bytes())?;
+        w.write_all(&frame.dmx)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.w.flush()?)
+    }
+}
+
+/// Reads frames written by a `Writer`.
+pub struct Reader<R: Read> {
+    r: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut r: R) -> Result<Self> {
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header).context("Not a recording")?;
+        ensure!(&header[..4] == MAGIC, "Not a recording");
+        ensure!(header[4] == VERSION, "Unsupported recording version {}", header[4]);
+        Ok(Self { r })
+    }
+
+    /// Read the next frame, or `None` at the end of the recording.
+    ///
+    /// A recording cut off partway through a frame, e.g. by a crash or power loss, ends at the last whole frame.
+    pub fn read(&mut self) -> Result<Option<Frame>> {
+        match self.frame() {
+            Err(e) if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::UnexpectedEof) => {
+                log::warn!("Recording ends partway through a frame, dropping it");
+                Ok(None)
+            }
+            res => res,
+        }
+    }
+
+    fn frame(&mut self) -> Result<Option<Frame>> {
+        // A complete recording ends between frames
+        let mut t = [0u8; 8];
+        if self.r.read(&mut t[..1])? == 0 {
+            return Ok(None);
+        }
+        self.r.read_exact(&mut t[1..])?;
+
+        let addr = match self.bytes::<1>()? {
+            [4] => IpAddr::V4(Ipv4Addr::from(self.bytes::<4>()?)),
+            [6] => IpAddr::V6(Ipv6Addr::from(self.bytes::<16>()?)),
+            [tag] => bail!("Invalid address tag {tag}"),
+        };
+        let universe = u16::from_le_bytes(self.bytes()?);
+        let len = u16::from_le_bytes(self.bytes()?);
+        let mut dmx = vec![0u8; len as usize];
+        self.r.read_exact(&mut dmx)?;
+
+        Ok(Some(Frame { t: Duration::from_micros(u64::from_le_bytes(t)), addr, universe, dmx }))
+    }
+
+    /// Read every remaining frame.
+    pub fn read_all(&mut self) -> Result<Vec<Frame>> {
+        let mut frames = vec![];
+        while let Some(frame) = self.read()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.r.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// An output which records every frame to a file before passing it on.
+///
+/// If the recording can't be written, e.g. with a full disk, recording stops but the frames still go out.
+pub struct Recorder {
+    inner: Box<dyn Output>,
+    /// `None` once recording has failed
+    writer: Option<Writer<Box<dyn Write + Send>>>,
+    start: Instant,
+    flushed: Instant,
+}
+
+impl Recorder {
+    pub fn new(inner: Box<dyn Output>, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Failed to create recording {}", path.display()))?;
+        log::info!("Recording DMX to {}", path.display());
+        Self::with_writer(inner, Box::new(BufWriter::new(file)))
+    }
+
+    fn with_writer(inner: Box<dyn Output>, w: Box<dyn Write + Send>) -> Result<Self> {
+        let now = Instant::now();
+        Ok(Self { inner, writer: Some(Writer::new(w)?), start: now, flushed: now })
+    }
+
+    fn record(&mut self, frame: &Frame) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        writer.write(frame)?;
+
+        // Flush regularly, since the engine thread may never get to drop us
+        if self.flushed.elapsed() >= FLUSH {
+            writer.flush()?;
+            self.flushed = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Output for Recorder {
+    fn send(&mut self, addr: &IpAddr, universe: u16, dmx: &[u8]) -> Result<()> {
+        let frame = Frame { t: self.start.elapsed(), addr: *addr, universe, dmx: dmx.to_vec() };
+        if let Err(e) = self.record(&frame) {
+            log::error!("Failed to record DMX, recording stopped: {e}");
+            self.writer = None;
+        }
+
+        self.inner.send(addr, universe, dmx)
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            writer.flush().ok();
+        }
+    }
+}
+
+/// Load every frame from a recording.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Frame>> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+    Reader::new(BufReader::new(file))?.read_all().with_context(|| format!("Invalid recording {}", path.display()))
+}
+
+/// Send recorded frames back out at their original timing, scaled by `speed`.
+/// Plays forever if `looping`.
+pub fn replay(frames: &[Frame], output: &mut dyn Output, speed: f64, looping: bool) -> Result<()> {
+    ensure!(!frames.is_empty(), "Recording is empty");
+    ensure!(speed > 0.0, "Replay speed must be positive");
+
+    loop {
+        let start = Instant::now();
+        for frame in frames {
+            let due = start + frame.t.div_f64(speed);
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            if let Err(e) = output.send(&frame.addr, frame.universe, &frame.dmx) {
+                log::debug!("Failed to send universe {} to {}: {e}", frame.universe, frame.addr);
+            }
+        }
+
+        if !looping {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Capture;
+
+    fn frame(ms: u64, addr: &str, universe: u16, dmx: Vec<u8>) -> Frame {
+        Frame { t: Duration::from_millis(ms), addr: addr.parse().unwrap(), universe, dmx }
+    }
+
+    fn frames() -> Vec<Frame> {
+        vec![
+            frame(0, "10.16.4.1", 1, vec![0, 255, 128, 0]),
+            frame(25, "fd00::2", 2, vec![0; 513]),
+            frame(50, "10.16.4.1", 1, vec![0, 1, 2, 3]),
+        ]
+    }
+
+    fn record(frames: &[Frame]) -> Vec<u8> {
+        let mut w = Writer::new(vec![]).unwrap();
+        for frame in frames {
+            w.write(frame).unwrap();
+        }
+        w.w
+    }
+
+    #[test]
+    fn roundtrip() {
+        let buf = record(&frames());
+        assert_eq!(Reader::new(&buf[..]).unwrap().read_all().unwrap(), frames());
+    }
+
+    #[test]
+    fn empty() {
+        let buf = record(&[]);
+        assert_eq!(Reader::new(&buf[..]).unwrap().read_all().unwrap(), []);
+    }
+
+    #[test]
+    fn cut_off() {
+        // Anywhere partway through the last frame, the first two are still there
+        let buf = record(&frames());
+        let last = record(&frames()[..2]).len();
+        for end in last + 1..buf.len() {
+            assert_eq!(Reader::new(&buf[..end]).unwrap().read_all().unwrap(), frames()[..2], "cut off at {end}");
+        }
+    }
+
+    #[test]
+    fn not_a_recording() {
+        assert!(Reader::new(&b"MSL"[..]).is_err());
+        assert!(Reader::new(&b"RIFF\x01"[..]).is_err());
+        assert!(Reader::new(&[b'M', b'S', b'L', b'V', VERSION + 1][..]).is_err());
+    }
+
+    /// Takes the first `n` bytes, then fails like a full disk.
+    struct Full {
+        n: usize,
+    }
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.n < buf.len() {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.n -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn recording_fails() {
+        // Room for the header and one frame, after which every frame still goes out
+        let capture = Capture::new(10);
+        let full = Full { n: record(&frames()[..1]).len() };
+        let mut recorder = Recorder::with_writer(Box::new(capture.clone()), Box::new(full)).unwrap();
+        for frame in frames() {
+            recorder.send(&frame.addr, frame.universe, &frame.dmx).unwrap();
+        }
+        assert!(recorder.writer.is_none());
+        let sent: Vec<_> = capture.sent().into_iter().map(|s| s.dmx).collect();
+        assert_eq!(sent, frames().into_iter().map(|f| f.dmx).collect::<Vec<_>>());
+    }
+}
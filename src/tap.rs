@@ -0,0 +1,222 @@
+use serde::Deserialize;
+
+/// Taps this long after the previous one start a new tempo, in seconds.
+const GAP: f64 = 2.0;
+/// Only the most recent taps count, so the estimate can follow a drifting tempo.
+const MAX_TAPS: usize = 16;
+/// How far an interval can stray from the typical one before it's rejected, as a fraction of it.
+const TOLERANCE: f64 = 0.2;
+
+/// Whether to round the tapped tempo.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Snap {
+    /// Use the tempo exactly as tapped
+    #[default]
+    Off,
+    /// Round to a whole BPM
+    Whole,
+    /// Round to a half BPM
+    Half,
+}
+
+impl Snap {
+    pub fn apply(self, bpm: f64) -> f64 {
+        match self {
+            Snap::Off => bpm,
+            Snap::Whole => bpm.round(),
+            Snap::Half => (bpm * 2.0).round() / 2.0,
+        }
+    }
+}
+
+/// Estimates a tempo from taps on a button.
+///
+/// Mistimed taps are rejected, and a missed tap or two doesn't throw the estimate off.
+#[derive(Clone, Debug, Default)]
+pub struct TapTempo {
+    /// Times of recent taps in seconds, oldest first
+    taps: Vec<f64>,
+    pub snap: Snap,
+}
+
+impl TapTempo {
+    pub fn new(snap: Snap) -> Self {
+        Self { taps: vec![], snap }
+    }
+
+    /// Record a tap at `t` seconds. Taps after a long pause start over.
+    pub fn tap(&mut self, t: f64) {
+        if self.taps.last().is_some_and(|&last| t - last > GAP) {
+            self.taps.clear();
+        }
+        self.taps.push(t);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.remove(0);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.taps.clear();
+    }
+
+    /// Move every tap by `dt` seconds, e.g. when the clock they were timed by jumps.
+    pub fn shift(&mut self, dt: f64) {
+        for t in &mut self.taps {
+            *t += dt;
+        }
+    }
+
+    /// Number of taps so far.
+    pub fn len(&self) -> usize {
+        self.taps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.taps.is_empty()
+    }
+
+    /// Whether taps are still coming in at `t` seconds.
+    pub fn tapping(&self, t: f64) -> bool {
+        self.taps.last().is_some_and(|&last| t - last <= GAP)
+    }
+
+    /// Time of the most recent tap.
+    pub fn last(&self) -> Option<f64> {
+        self.taps.last().copied()
+    }
+
+    /// The tempo tapped so far, or `None` until there are enough taps.
+    pub fn bpm(&self) -> Option<f64> {
+        self.period().map(|period| self.snap.apply(60.0 / period))
+    }
+
+    /// Seconds per beat, fit to the taps which agree with each other.
+    fn period(&self) -> Option<f64> {
+        let mut dts = self.taps.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+        if dts.is_empty() {
+            return None;
+        }
+        dts.sort_by(f64::total_cmp);
+        // The median is a good guess at the period, since it ignores a few bad intervals
+        let median = dts[dts.len() / 2];
+        if median <= 0.0 {
+            return None;
+        }
+
+        // Number each tap by the beat it landed on, counting missed beats and skipping taps far off the grid.
+        // Start from whichever tap keeps the most, preferring earlier ones, in case the first one was off.
+        let grid = |start: usize| {
+            let mut beats = vec![(0.0, self.taps[start])];
+            for &t in &self.taps[start + 1..] {
+                let (k, t0) = *beats.last().unwrap();
+                let n = ((t - t0) / median).round();
+                if n >= 1.0 && ((t - t0) - n * median).abs() <= TOLERANCE * median {
+                    beats.push((k + n, t));
+                }
+            }
+            beats
+        };
+        let beats = (0..self.taps.len()).rev().map(grid).max_by_key(|beats| beats.len()).unwrap();
+        if beats.len() < 2 {
+            return None;
+        }
+
+        // Least squares fit of tap time against beat number, whose slope is the period
+        let n = beats.len() as f64;
+        let (mk, mt) = (beats.iter().map(|b| b.0).sum::<f64>() / n, beats.iter().map(|b| b.1).sum::<f64>() / n);
+        let cov = beats.iter().map(|(k, t)| (k - mk) * (t - mt)).sum::<f64>();
+        let var = beats.iter().map(|(k, _)| (k - mk).powi(2)).sum::<f64>();
+        Some(cov / var)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timing errors in seconds, as a human tapping along might make.
+    const JITTER: [f64; 8] = [0.012, -0.018, 0.004, 0.021, -0.009, -0.015, 0.017, -0.006];
+
+    /// Tap `n` beats at `bpm` from `t0` seconds, with a little jitter on each.
+    fn taps(tap: &mut TapTempo, bpm: f64, t0: f64, n: usize) {
+        for i in 0..n {
+            tap.tap(t0 + i as f64 * 60.0 / bpm + JITTER[i % JITTER.len()]);
+        }
+    }
+
+    fn assert_near(bpm: Option<f64>, expected: f64) {
+        let bpm = bpm.expect("No tempo");
+        assert!((bpm - expected).abs() < 0.5, "Tapped {bpm}, expected {expected}");
+    }
+
+    #[test]
+    fn jittered() {
+        for expected in [87.0, 120.0, 128.0, 140.0, 174.0] {
+            let mut tap = TapTempo::default();
+            taps(&mut tap, expected, 10.0, 12);
+            assert_near(tap.bpm(), expected);
+        }
+    }
+
+    #[test]
+    fn not_enough_taps() {
+        let mut tap = TapTempo::default();
+        assert_eq!(tap.bpm(), None);
+        tap.tap(1.0);
+        assert_eq!(tap.bpm(), None);
+        tap.tap(1.5);
+        assert_near(tap.bpm(), 120.0);
+    }
+
+    #[test]
+    fn outlier() {
+        let mut tap = TapTempo::default();
+        taps(&mut tap, 128.0, 0.0, 6);
+        // Way early, then back on the beat
+        tap.tap(6.0 * 60.0 / 128.0 - 0.2);
+        taps(&mut tap, 128.0, 7.0 * 60.0 / 128.0, 6);
+        assert_near(tap.bpm(), 128.0);
+    }
+
+    #[test]
+    fn missed_tap() {
+        let mut tap = TapTempo::default();
+        let period = 60.0 / 124.0;
+        for beat in [0, 1, 2, 3, 5, 6, 7, 8] {
+            tap.tap(beat as f64 * period);
+        }
+        assert_near(tap.bpm(), 124.0);
+    }
+
+    #[test]
+    fn gap_starts_over() {
+        let mut tap = TapTempo::default();
+        taps(&mut tap, 90.0, 0.0, 8);
+        let last = tap.last().unwrap();
+        assert!(tap.tapping(last + GAP));
+        assert!(!tap.tapping(last + GAP + 0.1));
+
+        // The old taps are gone, so they don't drag the new tempo down
+        taps(&mut tap, 140.0, last + GAP + 0.5, 8);
+        assert_eq!(tap.len(), 8);
+        assert_near(tap.bpm(), 140.0);
+    }
+
+    #[test]
+    fn snap() {
+        let period = 60.0 / 127.7;
+        let bpm = |snap| {
+            let mut tap = TapTempo::new(snap);
+            (0..8).for_each(|i| tap.tap(i as f64 * period));
+            tap.bpm().unwrap()
+        };
+        assert!((bpm(Snap::Off) - 127.7).abs() < 1e-6);
+        assert_eq!(bpm(Snap::Whole), 128.0);
+        assert_eq!(bpm(Snap::Half), 127.5);
+
+        assert_eq!(Snap::Half.apply(127.76), 128.0);
+        assert_eq!(Snap::Half.apply(127.24), 127.0);
+        assert_eq!(Snap::Whole.apply(127.49), 127.0);
+    }
+}
@@ -0,0 +1,748 @@
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::audio::Source;
+use crate::clock::VIRTUAL;
+use crate::logic::{Action, Layout, Mode, Palette};
+use crate::midi;
+
+/// Timecode is considered gone after this long without a frame, in seconds.
+const DROPOUT: f64 = 0.2;
+/// Moving this far from where the timecode should be counts as a jump, in seconds.
+const JUMP: f64 = 1.0;
+/// Timecode can run backwards this far from jitter in delivery without counting as a jump, in seconds.
+const JITTER: f64 = 0.1;
+
+const QUARTER_FRAME: u8 = 0xf1;
+/// LTC sync word, as received from bit 64 to 79.
+const SYNC: u16 = 0xbffc;
+
+/// Frame rates timecode runs at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rate {
+    Fps24,
+    Fps25,
+    /// 29.97 frames per second, dropping frame labels to keep up with the clock
+    Drop30,
+    Fps30,
+}
+
+impl Rate {
+    /// Rate from the two bits MTC encodes it in.
+    fn from_bits(bits: u8) -> Self {
+        match bits & 3 {
+            0 => Rate::Fps24,
+            1 => Rate::Fps25,
+            2 => Rate::Drop30,
+            _ => Rate::Fps30,
+        }
+    }
+
+    /// Rate from a number of frames per second, like `25` or `29.97`.
+    pub fn from_fps(fps: f64) -> Option<Self> {
+        [Rate::Fps24, Rate::Fps25, Rate::Drop30, Rate::Fps30]
+            .into_iter()
+            .find(|r| (r.fps() - fps).abs() < 0.01)
+    }
+
+    /// Frames in each second of timecode labels.
+    pub fn frames(self) -> u8 {
+        match self {
+            Rate::Fps24 => 24,
+            Rate::Fps25 => 25,
+            Rate::Drop30 | Rate::Fps30 => 30,
+        }
+    }
+
+    /// Frames per second of real time.
+    pub fn fps(self) -> f64 {
+        match self {
+            Rate::Drop30 => 30000.0 / 1001.0,
+            _ => self.frames() as f64,
+        }
+    }
+}
+
+/// A frame label, like `01:02:03:04`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: Rate,
+}
+
+impl Timecode {
+    /// Seconds since `00:00:00:00`.
+    pub fn secs(&self) -> f64 {
+        let minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let mut frame = (minutes * 60 + self.seconds as u64) * self.rate.frames() as u64 + self.frames as u64;
+        // Drop frame skips the first two labels of every minute, except every tenth one
+        if self.rate == Rate::Drop30 {
+            frame -= 2 * (minutes - minutes / 10);
+        }
+        frame as f64 / self.rate.fps()
+    }
+
+    /// Parse `hh:mm:ss:ff` at `rate`.
+    pub fn parse(s: &str, rate: Rate) -> Result<Self> {
+        let parts = s.split([':', ';']).map(str::parse::<u8>).collect::<Result<Vec<_>, _>>();
+        let Ok(&[hours, minutes, seconds, frames]) = parts.as_deref() else {
+            bail!("Invalid timecode `{s}`, expected hh:mm:ss:ff");
+        };
+        ensure!(
+            hours < 24 && minutes < 60 && seconds < 60 && frames < rate.frames(),
+            "Timecode `{s}` is out of range at {} fps",
+            rate.fps()
+        );
+        Ok(Self { hours, minutes, seconds, frames, rate })
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sep = if self.rate == Rate::Drop30 { ';' } else { ':' };
+        write!(f, "{:02}:{:02}:{:02}{sep}{:02}", self.hours, self.minutes, self.seconds, self.frames)
+    }
+}
+
+/// A timecode position in seconds, and the time it was received at in seconds.
+pub type Fix = (f64, f64);
+
+/// Decodes MIDI Time Code.
+#[derive(Clone, Debug, Default)]
+pub struct Mtc {
+    /// Nibbles of the quarter frames received so far
+    pieces: [u8; 8],
+    /// Which pieces have arrived since piece 0
+    seen: u8,
+    /// Most recent timecode, and where it put us
+    latest: Option<(Timecode, Fix)>,
+}
+
+impl Mtc {
+    /// Handle a MIDI message received at `t` seconds.
+    pub fn handle(&mut self, msg: &[u8], t: f64) {
+        match *msg {
+            [QUARTER_FRAME, data] => {
+                let piece = (data >> 4 & 7) as usize;
+                // Start over with each timecode, so pieces of different ones aren't mixed up
+                if piece == 0 {
+                    self.seen = 0;
+                }
+                self.pieces[piece] = data & 0xf;
+                self.seen |= 1 << piece;
+
+                // Only running forwards, so piece 7 completes a timecode
+                if piece == 7 && self.seen == 0xff {
+                    let p = self.pieces;
+                    let tc = Timecode {
+                        hours: (p[7] & 1) << 4 | p[6],
+                        minutes: p[5] << 4 | p[4],
+                        seconds: p[3] << 4 | p[2],
+                        frames: p[1] << 4 | p[0],
+                        rate: Rate::from_bits(p[7] >> 1),
+                    };
+                    // The timecode is of the frame piece 0 was sent in, 7 quarter frames ago
+                    self.latest = Some((tc, (tc.secs() + 1.75 / tc.rate.fps(), t)));
+                }
+            }
+            // Full frame, sent when locating while stopped
+            [0xf0, 0x7f, _, 0x01, 0x01, hours, minutes, seconds, frames, 0xf7] => {
+                let tc = Timecode {
+                    hours: hours & 0x1f,
+                    minutes,
+                    seconds,
+                    frames,
+                    rate: Rate::from_bits(hours >> 5),
+                };
+                self.seen = 0;
+                self.latest = Some((tc, (tc.secs(), t)));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn latest(&self) -> Option<(Timecode, Fix)> {
+        self.latest
+    }
+}
+
+/// Decodes Linear Timecode from audio samples.
+#[derive(Clone, Debug)]
+pub struct Ltc {
+    sample_rate: f64,
+    /// Samples received so far
+    n: u64,
+    /// Whether the signal is above zero, with some hysteresis
+    high: bool,
+    /// Sample of the last transition
+    edge: u64,
+    /// Estimated samples per bit
+    period: f64,
+    /// Whether we're halfway through a 1 bit
+    half: bool,
+    /// The last 80 bits received, the newest at bit 79
+    bits: u128,
+    latest: Option<(Timecode, Fix)>,
+}
+
+impl Ltc {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate as f64,
+            n: 0,
+            high: false,
+            edge: 0,
+            // Between the slowest and fastest rates, so either kind of interval can be told apart to start with
+            period: sample_rate as f64 / (80.0 * 27.0),
+            half: false,
+            bits: 0,
+            latest: None,
+        }
+    }
+
+    /// Decode a chunk of samples, the first of which arrived at `t` seconds.
+    pub fn process(&mut self, samples: &[f32], t: f64) {
+        for (i, &x) in samples.iter().enumerate() {
+            self.n += 1;
+            let high = match self.high {
+                true => x > -0.02,
+                false => x > 0.02,
+            };
+            if high != self.high {
+                self.high = high;
+                let dt = (self.n - self.edge) as f64;
+                self.edge = self.n;
+                self.transition(dt, t + i as f64 / self.sample_rate);
+            }
+        }
+    }
+
+    /// Handle a transition `dt` samples after the last, at `t` seconds.
+    ///
+    /// Every bit starts with a transition, and 1 bits have another one halfway through.
+    fn transition(&mut self, dt: f64, t: f64) {
+        if dt > 1.5 * self.period {
+            // Way too long to be a bit, so the signal dropped out
+            self.half = false;
+        } else if dt > 0.75 * self.period {
+            self.period += (dt - self.period) * 0.1;
+            self.half = false;
+            self.push(false, t);
+        } else {
+            self.period += (dt * 2.0 - self.period) * 0.1;
+            if std::mem::take(&mut self.half) {
+                self.push(true, t);
+            } else {
+                self.half = true;
+            }
+        }
+    }
+
+    fn push(&mut self, bit: bool, t: f64) {
+        self.bits = self.bits >> 1 | (bit as u128) << 79;
+        if (self.bits >> 64) as u16 != SYNC {
+            return;
+        }
+
+        let b = self.bits;
+        let digit = |at: u32, len: u32| (b >> at & ((1 << len) - 1)) as u8;
+        let fps = self.sample_rate / self.period / 80.0;
+        let rate = match digit(10, 1) {
+            1 => Rate::Drop30,
+            _ => [Rate::Fps24, Rate::Fps25, Rate::Fps30]
+                .into_iter()
+                .min_by(|a, b| (a.fps() - fps).abs().total_cmp(&(b.fps() - fps).abs()))
+                .unwrap(),
+        };
+        let tc = Timecode {
+            hours: digit(56, 2) * 10 + digit(48, 4),
+            minutes: digit(40, 3) * 10 + digit(32, 4),
+            seconds: digit(24, 3) * 10 + digit(16, 4),
+            frames: digit(8, 2) * 10 + digit(0, 4),
+            rate,
+        };
+        // The sync word ends the frame, so the next one is starting now
+        self.latest = Some((tc, (tc.secs() + 1.0 / rate.fps(), t)));
+    }
+
+    pub fn latest(&self) -> Option<(Timecode, Fix)> {
+        self.latest
+    }
+}
+
+/// Whether we're following timecode as it arrives, or carrying on without it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lock {
+    Locked,
+    /// Timecode dropped out, so we're running on at the same speed for a while in case it comes back
+    Freewheel,
+}
+
+/// Where the timecode is now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    /// Seconds since `00:00:00:00`
+    pub secs: f64,
+    pub lock: Lock,
+    /// Whether the timecode jumped, or was picked up again, rather than running on from last time
+    pub jumped: bool,
+}
+
+/// Tracks the position of incoming timecode, freewheeling through dropouts and spotting jumps.
+#[derive(Clone, Debug)]
+pub struct Tracker {
+    /// Seconds to run on for when timecode drops out
+    pub freewheel: f64,
+    /// Position last time, if the timecode was being followed
+    last: Option<f64>,
+}
+
+impl Tracker {
+    pub fn new(freewheel: f64) -> Self {
+        Self { freewheel, last: None }
+    }
+
+    /// The position at `t` seconds, given the latest `fix`, or `None` if timecode has been gone too long.
+    pub fn track(&mut self, fix: Option<Fix>, t: f64) -> Option<Position> {
+        let Some((secs, at)) = fix.filter(|&(_, at)| t - at < DROPOUT + self.freewheel) else {
+            self.last = None;
+            return None;
+        };
+        let lock = if t - at < DROPOUT { Lock::Locked } else { Lock::Freewheel };
+        let secs = secs + (t - at).max(0.0);
+
+        let (secs, jumped) = match self.last {
+            Some(last) if secs - last > JUMP || last - secs > JITTER => (secs, true),
+            // Don't run backwards from a late frame
+            Some(last) => (secs.max(last), false),
+            None => (secs, true),
+        };
+        self.last = Some(secs);
+        Some(Position { secs, lock, jumped })
+    }
+}
+
+/// A change to make at a point in the show.
+#[derive(Clone, Debug)]
+pub struct Cue {
+    /// Timecode to make the change at, in seconds
+    pub at: f64,
+    /// Pad to press, selecting whatever mode or palette is there
+    pub pad: Option<(i8, i8)>,
+    /// Layout page the pad is on
+    pub page: usize,
+    /// Mode and palette to change to, without a pad for them
+    pub mode: Option<Mode>,
+    pub palette: Option<Palette>,
+    pub brightness: Option<f64>,
+}
+
+/// A timeline of cues, run as the timecode passes them.
+#[derive(Clone, Debug, Default)]
+pub struct Show {
+    /// Cues in order of time
+    cues: Vec<Cue>,
+    /// Index of the next cue to run
+    next: usize,
+}
+
+/// A show file, e.g.
+///
+/// ```toml
+/// fps = 25
+///
+/// [[cue]]
+/// at = "00:01:30:00"
+/// pad = [3, 2]
+/// page = 1
+///
+/// [[cue]]
+/// at = 95.5
+/// brightness = 0.6
+///
+/// [[cue]]
+/// at = "00:02:00:00"
+/// mode = { strobe = { pd = [1, 8], duty = 1.0 } }
+/// palette = { split = ["red", "blue"] }
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShowFile {
+    /// Frame rate of the timecodes in the file
+    #[serde(default)]
+    fps: Option<f64>,
+    #[serde(default)]
+    cue: Vec<CueFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CueFile {
+    at: At,
+    pad: Option<(i8, i8)>,
+    #[serde(default)]
+    page: usize,
+    mode: Option<Mode>,
+    palette: Option<Palette>,
+    brightness: Option<f64>,
+}
+
+/// When a cue happens, as a timecode or in seconds.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum At {
+    Secs(f64),
+    Timecode(String),
+}
+
+impl Show {
+    pub fn new(mut cues: Vec<Cue>) -> Self {
+        cues.sort_by(|a, b| a.at.total_cmp(&b.at));
+        Self { cues, next: 0 }
+    }
+
+    /// Load a show file, with cues pressing pads in `layout`.
+    pub fn load(path: impl AsRef<Path>, layout: &Layout) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read show {}", path.display()))?;
+        let show = Self::parse(&text, layout).with_context(|| format!("Invalid show {}", path.display()))?;
+        log::info!("Loaded {} cues from {}", show.len(), path.display());
+        Ok(show)
+    }
+
+    /// Parse and validate a show from a TOML string.
+    pub fn parse(text: &str, layout: &Layout) -> Result<Self> {
+        let file: ShowFile = toml::from_str(text)?;
+
+        let fps = file.fps.unwrap_or(30.0);
+        let rate = Rate::from_fps(fps).ok_or_else(|| anyhow!("Unsupported frame rate {fps}, expected 24, 25, 29.97, or 30"))?;
+
+        let cues = file
+            .cue
+            .into_iter()
+            .map(|cue| {
+                let at = match &cue.at {
+                    At::Secs(secs) => *secs,
+                    At::Timecode(tc) => Timecode::parse(tc, rate)?.secs(),
+                };
+                ensure!(
+                    cue.pad.is_some() || cue.mode.is_some() || cue.palette.is_some() || cue.brightness.is_some(),
+                    "Cue at {at}s doesn't change anything"
+                );
+                let pages = layout.pages().max(1);
+                ensure!(cue.page < pages, "Cue at {at}s is on page {}, but there are only {pages}", cue.page);
+                if let Some((x, y)) = cue.pad {
+                    let name = layout.name(cue.page);
+                    match layout.get(cue.page, x, y).map(|c| c.action) {
+                        None => bail!("Cue at {at}s presses pad ({x}, {y}) on page {name}, which does nothing"),
+                        // A cue never lets go, so it would be held forever
+                        Some(Action::Hold(_)) => bail!("Cue at {at}s presses pad ({x}, {y}) on page {name}, which only holds"),
+                        Some(_) => {}
+                    }
+                }
+                if let Some(brightness) = cue.brightness {
+                    ensure!((0.0..=1.0).contains(&brightness), "Cue at {at}s has brightness {brightness}, expected 0..1");
+                }
+                let CueFile { pad, page, mode, palette, brightness, .. } = cue;
+                Ok(Cue { at, pad, page, mode, palette, brightness })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(cues))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    /// Cues due by `pos`, since the last call.
+    ///
+    /// After a jump, every cue up to `pos` is due again, so the rig ends up as if the show had played through to there.
+    pub fn advance(&mut self, pos: &Position) -> &[Cue] {
+        let from = if pos.jumped { 0 } else { self.next };
+        let to = self.cues.partition_point(|cue| cue.at <= pos.secs);
+        self.next = to;
+        &self.cues[from.min(to)..to]
+    }
+}
+
+/// Follows timecode arriving over MIDI or as audio.
+pub struct TimecodeIn {
+    start: Instant,
+    latest: Arc<Mutex<Option<(Timecode, Fix)>>>,
+    tracker: Tracker,
+    /// Full name of the MIDI port, unless it's our own virtual one or the timecode is audio
+    port: Option<String>,
+    _conn: Option<MidiInputConnection<()>>,
+}
+
+impl TimecodeIn {
+    /// Follow MIDI Time Code on the input port best matching `name`, or a new virtual port if `name` is `virtual`.
+    pub fn mtc(name: &str, freewheel: f64) -> Result<Self> {
+        let mut input = MidiInput::new("mslive timecode")?;
+        // Full frames are SysEx, which is ignored by default
+        input.ignore(Ignore::None);
+
+        let start = Instant::now();
+        let latest = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&latest);
+        let mut mtc = Mtc::default();
+        let callback = move |_: u64, msg: &[u8], _: &mut ()| {
+            mtc.handle(msg, start.elapsed().as_secs_f64());
+            *shared.lock().unwrap() = mtc.latest();
+        };
+
+        let (port, conn) = match name {
+            #[cfg(unix)]
+            VIRTUAL => {
+                use midir::os::unix::VirtualInput;
+                log::info!("Following MIDI timecode on virtual port `mslive timecode`");
+                (None, input.create_virtual("mslive timecode", callback, ()).map_err(|e| anyhow!("{e}"))?)
+            }
+            _ => {
+                let ports = input.ports();
+                let names = ports.iter().map(|p| input.port_name(p).unwrap_or_default()).collect::<Vec<_>>();
+                let Some(i) = midi::find(&names, name).and_then(|found| names.iter().position(|n| n == found)) else {
+                    bail!("No MIDI input matches `{name}` for timecode, available inputs are: {names:?}");
+                };
+                log::info!("Following MIDI timecode on `{}`", names[i]);
+                let conn = input.connect(&ports[i], "mslive timecode", callback, ()).map_err(|e| anyhow!("{e}"))?;
+                (Some(names[i].clone()), conn)
+            }
+        };
+
+        Ok(Self { start, latest, tracker: Tracker::new(freewheel), port, _conn: Some(conn) })
+    }
+
+    /// Follow Linear Timecode from an audio file or input device.
+    pub fn ltc(source: Source, freewheel: f64) -> Result<Self> {
+        let (tx, rx) = mpsc::channel::<Vec<f32>>();
+        let rate = source.open(tx)?;
+        log::info!("Following linear timecode at {rate}Hz");
+
+        let start = Instant::now();
+        let latest = Arc::new(Mutex::new(None));
+        let shared = Arc::clone(&latest);
+        thread::spawn(move || {
+            let mut ltc = Ltc::new(rate);
+            for samples in rx {
+                ltc.process(&samples, start.elapsed().as_secs_f64());
+                *shared.lock().unwrap() = ltc.latest();
+            }
+        });
+
+        Ok(Self { start, latest, tracker: Tracker::new(freewheel), port: None, _conn: None })
+    }
+
+    /// Where the timecode is now, if it's running or freewheeling.
+    pub fn position(&mut self) -> Option<Position> {
+        let fix = self.latest.lock().unwrap().map(|(_, fix)| fix);
+        self.tracker.track(fix, self.start.elapsed().as_secs_f64())
+    }
+
+    /// Full name of the MIDI port followed, unless it's our own virtual one or the timecode is audio.
+    pub fn port(&self) -> Option<&str> {
+        self.port.as_deref()
+    }
+
+    /// The last timecode received, for display.
+    pub fn latest(&self) -> Option<Timecode> {
+        self.latest.lock().unwrap().map(|(tc, _)| tc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::pad_layout;
+    use crate::utils::Pd;
+    use itertools::Itertools;
+    use stagebridge::color::Rgbw;
+
+    const TC: Timecode = Timecode { hours: 1, minutes: 2, seconds: 3, frames: 4, rate: Rate::Fps25 };
+
+    /// Quarter frames for `tc`, starting from `piece`.
+    fn quarter_frames(tc: Timecode, piece: u8) -> Vec<[u8; 2]> {
+        let rate = match tc.rate {
+            Rate::Fps24 => 0,
+            Rate::Fps25 => 1,
+            Rate::Drop30 => 2,
+            Rate::Fps30 => 3,
+        };
+        let nibbles = [
+            tc.frames & 0xf,
+            tc.frames >> 4,
+            tc.seconds & 0xf,
+            tc.seconds >> 4,
+            tc.minutes & 0xf,
+            tc.minutes >> 4,
+            tc.hours & 0xf,
+            rate << 1 | tc.hours >> 4,
+        ];
+        (piece..8).map(|i| [QUARTER_FRAME, i << 4 | nibbles[i as usize]]).collect()
+    }
+
+    fn show() -> Show {
+        parse(
+            r#"
+            fps = 25
+
+            [[cue]]
+            at = 3.0
+            brightness = 0.5
+
+            [[cue]]
+            at = "00:00:01:00"
+            pad = [1, 2]
+
+            [[cue]]
+            at = 2.0
+            mode = { strobe = { pd = [1, 8], duty = 1.0 } }
+            palette = { split = ["red", [0.0, 0.0, 1.0, 0.0]] }
+            "#,
+        )
+        .unwrap()
+    }
+
+    fn parse(text: &str) -> Result<Show> {
+        Show::parse(text, &pad_layout().unwrap())
+    }
+
+    #[test]
+    fn mtc_quarter_frames() {
+        let mut mtc = Mtc::default();
+        for msg in quarter_frames(TC, 0) {
+            mtc.handle(&msg, 10.0);
+        }
+        // The timecode is of the first piece, so we're 7 quarter frames further along
+        assert_eq!(mtc.latest(), Some((TC, (TC.secs() + 1.75 / 25.0, 10.0))));
+    }
+
+    #[test]
+    fn mtc_partway_through() {
+        // Picked up partway through a timecode, wait for the next whole one
+        let mut mtc = Mtc::default();
+        for msg in quarter_frames(TC, 4) {
+            mtc.handle(&msg, 0.0);
+        }
+        assert_eq!(mtc.latest(), None);
+    }
+
+    #[test]
+    fn mtc_full_frame() {
+        let mut mtc = Mtc::default();
+        mtc.handle(&[0xf0, 0x7f, 0x7f, 0x01, 0x01, 1 << 5 | 1, 2, 3, 4, 0xf7], 5.0);
+        assert_eq!(mtc.latest(), Some((TC, (TC.secs(), 5.0))));
+    }
+
+    #[test]
+    fn timecode_secs() {
+        assert!((TC.secs() - (3723.0 + 4.0 / 25.0)).abs() < 1e-9);
+        assert_eq!(Timecode::parse("01:02:03:04", Rate::Fps25).unwrap(), TC);
+        assert!(Timecode::parse("01:02:03:25", Rate::Fps25).is_err());
+        // 10 minutes of drop frame labels keep up with 10 minutes of real time
+        let tc = Timecode::parse("00:10:00;00", Rate::Drop30).unwrap();
+        assert!((tc.secs() - 600.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tracker_dropout() {
+        let mut tracker = Tracker::new(2.0);
+        let fix = Some((10.0, 0.0));
+
+        // Running on from the last frame
+        let pos = tracker.track(fix, 0.1).unwrap();
+        assert_eq!(pos, Position { secs: 10.1, lock: Lock::Locked, jumped: true });
+        let pos = tracker.track(fix, 0.15).unwrap();
+        assert_eq!(pos, Position { secs: 10.15, lock: Lock::Locked, jumped: false });
+
+        // Dropped out, so freewheel for a while, then give up
+        let pos = tracker.track(fix, 1.0).unwrap();
+        assert_eq!(pos, Position { secs: 11.0, lock: Lock::Freewheel, jumped: false });
+        assert_eq!(tracker.track(fix, 2.3), None);
+
+        // Picked up again
+        let pos = tracker.track(Some((20.0, 2.3)), 2.3).unwrap();
+        assert_eq!(pos, Position { secs: 20.0, lock: Lock::Locked, jumped: true });
+    }
+
+    #[test]
+    fn tracker_no_freewheel() {
+        let mut tracker = Tracker::new(0.0);
+        assert!(tracker.track(Some((10.0, 0.0)), 0.1).is_some());
+        assert_eq!(tracker.track(Some((10.0, 0.0)), 0.3), None);
+        assert_eq!(tracker.track(None, 0.3), None);
+    }
+
+    #[test]
+    fn tracker_jumps() {
+        let mut tracker = Tracker::new(2.0);
+        assert!(tracker.track(Some((10.0, 0.0)), 0.0).unwrap().jumped);
+
+        // A late frame doesn't run backwards
+        let pos = tracker.track(Some((10.0, 0.0)), 0.05).unwrap();
+        assert_eq!((pos.secs, pos.jumped), (10.05, false));
+        let pos = tracker.track(Some((9.96, 0.05)), 0.05).unwrap();
+        assert_eq!((pos.secs, pos.jumped), (10.05, false));
+
+        // Located forwards or backwards
+        let pos = tracker.track(Some((30.0, 0.1)), 0.1).unwrap();
+        assert_eq!((pos.secs, pos.jumped), (30.0, true));
+        let pos = tracker.track(Some((5.0, 0.15)), 0.15).unwrap();
+        assert_eq!((pos.secs, pos.jumped), (5.0, true));
+    }
+
+    #[test]
+    fn show_file() {
+        let show = show();
+        assert_eq!(show.cues.iter().map(|c| c.at).collect_vec(), [1.0, 2.0, 3.0]);
+        assert_eq!(show.cues[0].pad, Some((1, 2)));
+        assert!(matches!(show.cues[1].mode, Some(Mode::Strobe { pd: Pd(1, 8), duty: 1.0 })));
+        let Some(Palette::Split(Rgbw(r0, g0, b0, _), Rgbw(r1, g1, b1, _))) = show.cues[1].palette else {
+            panic!("{:?}", show.cues[1].palette);
+        };
+        assert_eq!([r0, g0, b0, r1, g1, b1], [1.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        assert!(parse("[[cue]]\nat = 1.0").is_err());
+        assert!(parse("[[cue]]\nat = 1.0\npalette = { solid = \"beige\" }").is_err());
+        assert!(parse("fps = 60\n[[cue]]\nat = \"00:00:01:00\"\nbrightness = 1.0").is_err());
+
+        // Pads have to be laid out, on a page that exists
+        assert!(parse("[[cue]]\nat = 1.0\npad = [1, 0]\npage = 2").is_ok());
+        assert!(parse("[[cue]]\nat = 1.0\npad = [8, 0]").is_err());
+        assert!(parse("[[cue]]\nat = 1.0\npad = [6, 6]\npage = 2").is_err());
+        assert!(parse("[[cue]]\nat = 1.0\nbrightness = 1.0\npage = 5").is_err());
+
+        // Holds would never be let go
+        assert!(parse("[[cue]]\nat = 1.0\npad = [1, 7]\npage = 4").is_err());
+    }
+
+    #[test]
+    fn show_advance() {
+        let mut show = show();
+        let pos = |secs, jumped| Position { secs, lock: Lock::Locked, jumped };
+        let at = |cues: &[Cue]| cues.iter().map(|c| c.at).collect_vec();
+
+        assert_eq!(at(show.advance(&pos(0.5, true))), []);
+        assert_eq!(at(show.advance(&pos(2.0, false))), [1.0, 2.0]);
+        assert_eq!(at(show.advance(&pos(2.5, false))), []);
+        assert_eq!(at(show.advance(&pos(3.0, false))), [3.0]);
+
+        // After a jump, everything up to there runs again
+        assert_eq!(at(show.advance(&pos(2.5, true))), [1.0, 2.0]);
+        assert_eq!(at(show.advance(&pos(4.0, false))), [3.0]);
+    }
+}
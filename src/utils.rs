@@ -1,12 +1,14 @@
+use serde::{Deserialize, Deserializer};
 use std::ops::Deref;
 use std::rc::Rc;
 
+use stagebridge::color::Rgbw;
 use stagebridge::prelude::*;
 
 use crate::State;
 
 /// Pd
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize)]
 pub struct Pd(pub usize, pub usize);
 impl Pd {
     pub fn fr(&self) -> f64 {
@@ -18,6 +20,13 @@ impl Pd {
     pub fn div(&self, div: usize) -> Self {
         Self(self.0, self.1 * div)
     }
+    /// Faster by a factor of `2^exp`, or slower for negative `exp`.
+    pub fn speed(&self, exp: i8) -> Self {
+        match exp {
+            0.. => self.div(1 << exp),
+            _ => self.mul(1 << -exp),
+        }
+    }
 }
 
 impl Default for Pd {
@@ -26,6 +35,43 @@ impl Default for Pd {
     }
 }
 
+/// Deserialize a `Range` from `[lo, hi]`.
+pub fn de_range<'de, D: Deserializer<'de>>(d: D) -> Result<Range, D::Error> {
+    let (lo, hi) = <(f64, f64)>::deserialize(d)?;
+    Ok((lo..hi).into())
+}
+
+/// Deserialize a color from its name, like `"red"`, or from `[r, g, b, w]`.
+pub fn de_color<'de, D: Deserializer<'de>>(d: D) -> Result<Rgbw, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Color {
+        Name(String),
+        Rgbw(f64, f64, f64, f64),
+    }
+
+    Ok(match Color::deserialize(d)? {
+        Color::Rgbw(r, g, b, w) => Rgbw(r, g, b, w),
+        Color::Name(name) => match name.as_str() {
+            "black" => Rgbw::BLACK,
+            "white" => Rgbw::WHITE,
+            "rgbw" => Rgbw::RGBW,
+            "red" => Rgbw::RED,
+            "orange" => Rgbw::ORANGE,
+            "yellow" => Rgbw::YELLOW,
+            "lime" => Rgbw::LIME,
+            "green" => Rgbw::GREEN,
+            "mint" => Rgbw::MINT,
+            "cyan" => Rgbw::CYAN,
+            "blue" => Rgbw::BLUE,
+            "violet" => Rgbw::VIOLET,
+            "magenta" => Rgbw::MAGENTA,
+            "pink" => Rgbw::PINK,
+            _ => return Err(serde::de::Error::custom(format!("unknown color `{name}`"))),
+        },
+    })
+}
+
 /// A helper to keep track of a momentary "hold" of a button.
 ///
 /// Like a fancy `Option<T>` that remembers who set it to `Some`.
@@ -46,17 +92,19 @@ pub enum Hold<T> {
 impl<T> Hold<T> {
     /// Trigger a hold. `hold(true, T)` starts the hold, `hold(false, T)` ends it.
     pub fn hold(&mut self, x: i8, y: i8, pressed: bool, val: T) {
-        let released = !pressed;
-
         if pressed {
             // When a button is pressed, update the hold state
             *self = Self::Held { x, y, val };
-        } else if released {
-            // When a button is released, only reset hold state if it was set at the same coords
-            if let Hold::Held { x: x0, y: y0, .. } = self {
-                if x0 == y0 {
-                    *self = Self::Off;
-                }
+        } else {
+            self.release(x, y);
+        }
+    }
+
+    /// End the hold, but only if it was started by the button at `x`, `y`.
+    pub fn release(&mut self, x: i8, y: i8) {
+        if let Hold::Held { x: x0, y: y0, .. } = *self {
+            if (x0, y0) == (x, y) {
+                *self = Self::Off;
             }
         }
     }
@@ -70,6 +118,39 @@ impl<T> Hold<T> {
     }
 }
 
+/// Momentary holds of the same thing by several buttons at once.
+///
+/// The latest press is on top, and letting go of it goes back to whatever is still held underneath.
+#[derive(Clone, Debug)]
+pub struct HoldStack<T> {
+    /// Page and coordinates of each button holding, and its value, oldest first
+    held: Vec<(usize, i8, i8, T)>,
+}
+
+impl<T> Default for HoldStack<T> {
+    fn default() -> Self {
+        Self { held: vec![] }
+    }
+}
+
+impl<T> HoldStack<T> {
+    /// Start a hold by the button at `x`, `y` on `page`, on top of any others.
+    pub fn hold(&mut self, page: usize, x: i8, y: i8, val: T) {
+        self.release(page, x, y);
+        self.held.push((page, x, y, val));
+    }
+
+    /// End the hold started by the button at `x`, `y` on `page`, wherever it is in the stack.
+    pub fn release(&mut self, page: usize, x: i8, y: i8) {
+        self.held.retain(|&(p, x0, y0, _)| (p, x0, y0) != (page, x, y));
+    }
+
+    /// Return the latest value still being held.
+    pub fn value(&self) -> Option<&T> {
+        self.held.last().map(|(_, _, _, val)| val)
+    }
+}
+
 /// Beat
 #[derive(Default, Clone, Copy)]
 pub enum Beat {